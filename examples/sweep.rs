@@ -16,7 +16,7 @@ fn main() {
 
     for _n in 0..60 {
         // Slowly lower pitch every frame
-        period = period + 3;
+        period += 3;
 
         // Generate 1/60 second of input clocks. We could generate
         // any number of clocks here, all the way down to 1.
@@ -24,11 +24,11 @@ fn main() {
         while time < clocks {
             blip.add_delta(time as u32, delta).unwrap();
             delta = -delta; // square wave deltas alternate sign
-            time = time + period;
+            time += period;
         }
 
         // Add those clocks to buffer and adjust time for next frame
-        time = time - clocks;
+        time -= clocks;
         blip.end_frame(clocks as u32).unwrap();
 
         // Read and play any output samples now available
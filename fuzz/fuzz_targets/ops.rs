@@ -0,0 +1,84 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use blip_buf::{BlipBuf, MAX_FRAME};
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Arbitrary, Debug)]
+enum Op {
+    SetRates { sample_rate: u16, ratio: u32 },
+    AddDelta { time: u32, delta: i32 },
+    AddDeltaFast { time: u32, delta: i32 },
+    EndFrame { duration: u32 },
+    ClocksNeeded { count: u16 },
+    ReadSamples { count: u16, stereo: bool },
+    Clear,
+    ClockDomain { sample_rate: u16, ratio: u32 },
+    AddDeltaIn { time: u32, delta: i32 },
+    DomainClocks { duration: u32 },
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    size: u16,
+    ops: Vec<Op>,
+}
+
+fuzz_target!(|input: Input| {
+    let size = input.size as u32;
+    let mut blip = BlipBuf::new(size);
+    let mut buf = vec![0i16; u16::MAX as usize];
+    let mut domain = None;
+
+    for op in input.ops.into_iter().take(256) {
+        match op {
+            Op::SetRates { sample_rate, ratio } => {
+                let sample_rate = sample_rate.max(1) as f64;
+                let clock_rate = sample_rate * (ratio as f64 / 4096.0).max(1.0);
+                let _ = blip.set_rates(clock_rate, sample_rate);
+            }
+            Op::AddDelta { time, delta } => {
+                let _ = blip.add_delta(time, delta);
+            }
+            Op::AddDeltaFast { time, delta } => {
+                let _ = blip.add_delta_fast(time, delta);
+            }
+            Op::EndFrame { duration } => {
+                let _ = blip.end_frame(duration);
+            }
+            Op::ClocksNeeded { count } => {
+                let avail = blip.samples_avail();
+                let count = count as u32;
+                match blip.clocks_needed(count) {
+                    Ok(clocks) => {
+                        blip.end_frame(clocks).unwrap();
+                        assert_eq!(blip.samples_avail(), avail + count);
+                    }
+                    Err(_) => assert!(avail + count > size || count as u64 > MAX_FRAME),
+                }
+            }
+            Op::ReadSamples { count, stereo } => {
+                let avail = blip.samples_avail() as usize;
+                let read = blip.read_samples(&mut buf[..count as usize], stereo);
+                let step = if stereo { 2 } else { 1 };
+                assert_eq!(read, avail.min(count as usize / step));
+            }
+            Op::Clear => blip.clear(),
+            Op::ClockDomain { sample_rate, ratio } => {
+                let sample_rate = sample_rate.max(1) as f64;
+                let clock_rate = sample_rate * (ratio as f64 / 4096.0).max(1.0);
+                domain = blip.clock_domain(clock_rate, sample_rate).ok();
+            }
+            Op::AddDeltaIn { time, delta } => {
+                if let Some(domain) = domain {
+                    let _ = blip.add_delta_in(domain, time, delta);
+                }
+            }
+            Op::DomainClocks { duration } => {
+                if let Some(domain) = domain {
+                    let _ = blip.domain_clocks(domain, duration);
+                }
+            }
+        }
+    }
+});
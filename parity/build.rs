@@ -0,0 +1,8 @@
+fn main() {
+    println!("cargo:rerun-if-changed=c/blip_buf.c");
+    println!("cargo:rerun-if-changed=c/blip_buf.h");
+    cc::Build::new()
+        .file("c/blip_buf.c")
+        .include("c")
+        .compile("blip_buf_c");
+}
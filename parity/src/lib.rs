@@ -0,0 +1,90 @@
+//! The original C blip_buf, vendored in `c/` and built by `build.rs`, so tests
+//! can check that `blip_buf` produces identical output. Not published.
+//!
+//! The C library asserts on invalid use instead of returning errors, which
+//! aborts the test process, so callers must stay within its limits.
+
+use std::os::raw::{c_double, c_int, c_short, c_uint};
+
+#[repr(C)]
+struct blip_t {
+    _private: [u8; 0],
+}
+
+extern "C" {
+    fn blip_new(sample_count: c_int) -> *mut blip_t;
+    fn blip_set_rates(m: *mut blip_t, clock_rate: c_double, sample_rate: c_double);
+    fn blip_clear(m: *mut blip_t);
+    fn blip_add_delta(m: *mut blip_t, clock_time: c_uint, delta: c_int);
+    fn blip_add_delta_fast(m: *mut blip_t, clock_time: c_uint, delta: c_int);
+    fn blip_clocks_needed(m: *const blip_t, sample_count: c_int) -> c_int;
+    fn blip_end_frame(m: *mut blip_t, clock_duration: c_uint);
+    fn blip_samples_avail(m: *const blip_t) -> c_int;
+    fn blip_read_samples(m: *mut blip_t, out: *mut c_short, count: c_int, stereo: c_int) -> c_int;
+    fn blip_delete(m: *mut blip_t);
+}
+
+/// Owned C `blip_t`, with the same methods as `blip_buf::BlipBuf`.
+pub struct CBlipBuf {
+    ptr: *mut blip_t,
+}
+
+impl CBlipBuf {
+    /// Creates new buffer that can hold at most sample_count samples.
+    pub fn new(sample_count: u32) -> Self {
+        let ptr = unsafe { blip_new(sample_count as c_int) };
+        assert!(!ptr.is_null(), "out of memory");
+        Self { ptr }
+    }
+
+    /// Sets approximate input clock rate and output sample rate.
+    pub fn set_rates(&mut self, clock_rate: f64, sample_rate: f64) {
+        unsafe { blip_set_rates(self.ptr, clock_rate, sample_rate) }
+    }
+
+    /// Clears entire buffer.
+    pub fn clear(&mut self) {
+        unsafe { blip_clear(self.ptr) }
+    }
+
+    /// Adds positive/negative delta into buffer at specified clock time.
+    pub fn add_delta(&mut self, clock_time: u32, delta: i32) {
+        unsafe { blip_add_delta(self.ptr, clock_time, delta) }
+    }
+
+    /// Same as `add_delta()`, but uses faster, lower-quality synthesis.
+    pub fn add_delta_fast(&mut self, clock_time: u32, delta: i32) {
+        unsafe { blip_add_delta_fast(self.ptr, clock_time, delta) }
+    }
+
+    /// Length of time frame, in clocks, needed to make `sample_count`
+    /// additional samples available.
+    pub fn clocks_needed(&self, sample_count: u32) -> u32 {
+        unsafe { blip_clocks_needed(self.ptr, sample_count as c_int) as u32 }
+    }
+
+    /// Makes input clocks before `clock_duration` available for reading.
+    pub fn end_frame(&mut self, clock_duration: u32) {
+        unsafe { blip_end_frame(self.ptr, clock_duration) }
+    }
+
+    /// Number of buffered samples available for reading.
+    pub fn samples_avail(&self) -> u32 {
+        unsafe { blip_samples_avail(self.ptr) as u32 }
+    }
+
+    /// Reads and removes at most `buf.len()` samples, or half as many if
+    /// `stereo` is true. Returns number of samples actually read.
+    pub fn read_samples(&mut self, buf: &mut [i16], stereo: bool) -> usize {
+        let count = if stereo { buf.len() / 2 } else { buf.len() };
+        unsafe {
+            blip_read_samples(self.ptr, buf.as_mut_ptr(), count as c_int, stereo as c_int) as usize
+        }
+    }
+}
+
+impl Drop for CBlipBuf {
+    fn drop(&mut self) {
+        unsafe { blip_delete(self.ptr) }
+    }
+}
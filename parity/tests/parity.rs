@@ -0,0 +1,97 @@
+use blip_buf::BlipBuf;
+use blip_buf_parity::CBlipBuf;
+
+/* xorshift64*, so runs are reproducible without extra dependencies */
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n.max(1)
+    }
+
+    /* Steps to a new random level, so the waveform stays in range like a
+    real chip's would */
+    fn delta(&mut self, level: &mut i32) -> i32 {
+        let new_level = self.below(40001) as i32 - 20000;
+        let delta = new_level - *level;
+        *level = new_level;
+        delta
+    }
+}
+
+fn run(seed: u64) {
+    let mut rng = Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1);
+    let size = 100 + rng.below(4000) as u32;
+    let sample_rate = [8000.0, 22050.0, 44100.0, 48000.0, 96000.0][rng.below(5) as usize];
+    let clock_rate = sample_rate * (1.0 + rng.below(1 << 16) as f64 / 16.0);
+
+    let mut rust = BlipBuf::new(size);
+    let mut c = CBlipBuf::new(size);
+    rust.set_rates(clock_rate, sample_rate).unwrap();
+    c.set_rates(clock_rate, sample_rate);
+
+    let mut expected = vec![0i16; size as usize * 2];
+    let mut actual = vec![0i16; size as usize * 2];
+    let mut level = 0;
+    for step in 0..200 {
+        if rng.below(50) == 0 {
+            rust.clear();
+            c.clear();
+            level = 0;
+        }
+
+        let room = size - rust.samples_avail();
+        let wanted = rng.below(room as u64 + 1) as u32;
+        let clocks = c.clocks_needed(wanted);
+        assert_eq!(
+            rust.clocks_needed(wanted),
+            Ok(clocks),
+            "seed {seed} step {step}"
+        );
+
+        /* At most one step every other sample, as more can overflow the
+        32-bit sums in either implementation */
+        let steps = rng.below(wanted as u64 / 2 + 1).min(100);
+        let mut times: Vec<u32> = (0..steps)
+            .map(|_| rng.below(clocks as u64 + 1) as u32)
+            .collect();
+        times.sort_unstable();
+        for time in times {
+            let delta = rng.delta(&mut level);
+            if rng.below(4) == 0 {
+                rust.add_delta_fast(time, delta).unwrap();
+                c.add_delta_fast(time, delta);
+            } else {
+                rust.add_delta(time, delta).unwrap();
+                c.add_delta(time, delta);
+            }
+        }
+        rust.end_frame(clocks).unwrap();
+        c.end_frame(clocks);
+        assert_eq!(
+            rust.samples_avail(),
+            c.samples_avail(),
+            "seed {seed} step {step}"
+        );
+
+        let stereo = rng.below(2) == 0;
+        let len = rng.below(rust.samples_avail() as u64 + 1) as usize * if stereo { 2 } else { 1 };
+        let read = c.read_samples(&mut expected[..len], stereo);
+        assert_eq!(rust.read_samples(&mut actual[..len], stereo), read);
+        assert_eq!(actual[..len], expected[..len], "seed {seed} step {step}");
+    }
+}
+
+#[test]
+fn matches_c_library() {
+    for seed in 0..200 {
+        run(seed);
+    }
+}
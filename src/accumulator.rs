@@ -0,0 +1,65 @@
+use std::ops::{Add, Mul};
+
+/// Type of the sums a `BlipBuf` accumulates deltas into, see
+/// `BlipBuf::with_accumulator()`. Implemented for `i32`, the default, and
+/// `i64`, which is twice as large but lets many chips be summed at high
+/// amplitude before the output stage.
+///
+/// Each delta is added as `delta << 15`, spread over several samples, so `i32`
+/// sums hold waveforms within about ±65536 exactly. Sums that would overflow
+/// saturate instead, which distorts the output but never panics.
+pub trait Accumulator:
+    Copy
+    + Default
+    + From<i32>
+    + Into<i64>
+    + Add<Output = Self>
+    + Mul<Output = Self>
+    + Send
+    + Sync
+    + sealed::Sealed
+{
+    /// Largest delta magnitude `add_delta()` and friends accept. The step of a
+    /// delta this large fits in this type on its own.
+    const MAX_DELTA: i32;
+
+    /// Converts `n`, saturating at the bounds of this type.
+    fn saturate(n: i64) -> Self;
+
+    /// Adds `other`, saturating at the bounds of this type.
+    fn saturating_add(self, other: Self) -> Self;
+}
+
+impl Accumulator for i32 {
+    const MAX_DELTA: i32 = crate::MAX_DELTA;
+
+    #[inline]
+    fn saturate(n: i64) -> Self {
+        n.clamp(i32::MIN.into(), i32::MAX.into()) as i32
+    }
+
+    #[inline]
+    fn saturating_add(self, other: Self) -> Self {
+        i32::saturating_add(self, other)
+    }
+}
+
+impl Accumulator for i64 {
+    const MAX_DELTA: i32 = i32::MAX;
+
+    #[inline]
+    fn saturate(n: i64) -> Self {
+        n
+    }
+
+    #[inline]
+    fn saturating_add(self, other: Self) -> Self {
+        i64::saturating_add(self, other)
+    }
+}
+
+mod sealed {
+    pub trait Sealed {}
+    impl Sealed for i32 {}
+    impl Sealed for i64 {}
+}
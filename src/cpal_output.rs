@@ -0,0 +1,212 @@
+//! Adapter that plays the samples of a split `BlipBuf` through a [cpal] output
+//! stream.
+//!
+//! Only available with the `cpal` feature enabled.
+//!
+//! [cpal]: https://docs.rs/cpal
+
+use cpal::traits::DeviceTrait;
+use cpal::{BuildStreamError, FromSample, SizedSample, StreamConfig, StreamError};
+
+use crate::Consumer;
+
+/// Feeds a `Consumer` into cpal output callbacks, converting samples to the
+/// stream's format and copying the mono signal to every channel. Whatever the
+/// consumer can't supply is filled with silence and counted as an underrun.
+pub struct CpalOutput {
+    consumer: Consumer,
+    channels: usize,
+}
+
+impl CpalOutput {
+    /// Creates adapter for a stream with `channels` interleaved channels.
+    pub fn new(consumer: Consumer, channels: u16) -> Self {
+        Self {
+            consumer,
+            channels: channels.max(1) as usize,
+        }
+    }
+
+    /// The wrapped `Consumer`, for querying its counters.
+    pub fn consumer(&self) -> &Consumer {
+        &self.consumer
+    }
+
+    /// Fills `data` with interleaved frames, as a cpal data callback would. Never
+    /// blocks or allocates.
+    pub fn fill<T: SizedSample + FromSample<i16>>(&mut self, data: &mut [T]) {
+        let temp = &mut [0i16; 256];
+        for chunk in data.chunks_mut(temp.len() * self.channels) {
+            let frames = chunk.len() / self.channels;
+            let count = self.consumer.read_samples(&mut temp[..frames], false);
+
+            for (frame, &s) in chunk.chunks_mut(self.channels).zip(&temp[..count]) {
+                frame.fill(T::from_sample(s));
+            }
+            chunk[count * self.channels..].fill(T::EQUILIBRIUM);
+        }
+    }
+
+    /// Builds an output stream on `device` that plays from this adapter. The
+    /// stream's sample type `T` must match the device's supported format.
+    pub fn build_output_stream<T, E, D>(
+        mut self,
+        device: &D,
+        config: &StreamConfig,
+        error_callback: E,
+    ) -> Result<D::Stream, BuildStreamError>
+    where
+        T: SizedSample + FromSample<i16>,
+        E: FnMut(StreamError) + Send + 'static,
+        D: OutputDevice<T>,
+    {
+        self.channels = config.channels.max(1) as usize;
+        device.build_stream(config, move |data| self.fill(data), error_callback)
+    }
+}
+
+/// Device that can play an output stream of samples of type `T`. Implemented
+/// for all cpal devices; a mock host can implement it to test stream setup
+/// without sound hardware.
+pub trait OutputDevice<T: SizedSample> {
+    /// Stream that plays for as long as it is kept.
+    type Stream;
+
+    /// Builds an output stream that calls `data_callback` to fill each buffer
+    /// of interleaved samples, like `DeviceTrait::build_output_stream()`.
+    fn build_stream<D, E>(
+        &self,
+        config: &StreamConfig,
+        data_callback: D,
+        error_callback: E,
+    ) -> Result<Self::Stream, BuildStreamError>
+    where
+        D: FnMut(&mut [T]) + Send + 'static,
+        E: FnMut(StreamError) + Send + 'static;
+}
+
+impl<T: SizedSample, Dev: DeviceTrait> OutputDevice<T> for Dev {
+    type Stream = Dev::Stream;
+
+    fn build_stream<D, E>(
+        &self,
+        config: &StreamConfig,
+        mut data_callback: D,
+        error_callback: E,
+    ) -> Result<Dev::Stream, BuildStreamError>
+    where
+        D: FnMut(&mut [T]) + Send + 'static,
+        E: FnMut(StreamError) + Send + 'static,
+    {
+        self.build_output_stream(
+            config,
+            move |data: &mut [T], _| data_callback(data),
+            error_callback,
+            None,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CpalOutput, OutputDevice};
+    use crate::test_util::new_blip;
+    use cpal::{BufferSize, BuildStreamError, SampleRate, StreamConfig, StreamError};
+
+    /* Plays a single buffer of `frames` frames as soon as the stream is built,
+    and returns it as the stream */
+    struct MockDevice {
+        frames: usize,
+    }
+
+    impl OutputDevice<f32> for MockDevice {
+        type Stream = Vec<f32>;
+
+        fn build_stream<D, E>(
+            &self,
+            config: &StreamConfig,
+            mut data_callback: D,
+            _error_callback: E,
+        ) -> Result<Vec<f32>, BuildStreamError>
+        where
+            D: FnMut(&mut [f32]) + Send + 'static,
+            E: FnMut(StreamError) + Send + 'static,
+        {
+            if config.channels == 0 {
+                return Err(BuildStreamError::StreamConfigNotSupported);
+            }
+            let mut data = vec![f32::NAN; self.frames * config.channels as usize];
+            data_callback(&mut data);
+            Ok(data)
+        }
+    }
+
+    fn config(channels: u16) -> StreamConfig {
+        StreamConfig {
+            channels,
+            sample_rate: SampleRate(48000),
+            buffer_size: BufferSize::Default,
+        }
+    }
+
+    fn output(queued: u32) -> CpalOutput {
+        let blip = new_blip(1000000.0);
+        let (mut producer, consumer) = blip.split(4800);
+        producer.add_delta(0, 20000).unwrap();
+        producer
+            .end_frame(producer.clocks_needed(queued).unwrap())
+            .unwrap();
+        CpalOutput::new(consumer, 2)
+    }
+
+    #[test]
+    fn converts_and_duplicates() {
+        let mut blip = new_blip(1000000.0);
+        blip.add_delta(0, 20000).unwrap();
+        blip.end_frame(blip.clocks_needed(600).unwrap()).unwrap();
+        let expected = &mut [0i16; 600];
+        blip.read_samples(expected, false);
+
+        let mut out = output(600);
+        let data = &mut [0.0f32; 1200];
+        out.fill(data);
+        for (frame, &s) in data.chunks(2).zip(expected.iter()) {
+            assert_eq!(frame, &[s as f32 / 32768.0; 2]);
+        }
+        assert_eq!(out.consumer().underruns(), 0);
+    }
+
+    #[test]
+    fn silence_on_underrun() {
+        let mut out = output(100);
+        let data = &mut [1u16; 300];
+        out.fill(data);
+        assert!(data[..200].iter().any(|&s| s != 32768));
+        assert!(data[200..].iter().all(|&s| s == 32768));
+        assert_eq!(out.consumer().underruns(), 50);
+    }
+
+    #[test]
+    fn stream_on_mock_device() {
+        let mut reference = output(100);
+        let expected = &mut [0.0f32; 200];
+        reference.fill(expected);
+
+        /* The stream's channel count wins over the one passed to new() */
+        let device = MockDevice { frames: 120 };
+        let played = output(100)
+            .build_output_stream::<f32, _, _>(&device, &config(3), |_| {})
+            .unwrap();
+        assert_eq!(played.len(), 360);
+        for (frame, expected) in played.chunks(3).zip(expected.chunks(2)) {
+            assert_eq!(frame, &[expected[0]; 3]);
+        }
+        assert!(played[300..].iter().all(|&s| s == 0.0));
+
+        let error = output(100).build_output_stream::<f32, _, _>(&device, &config(0), |_| {});
+        assert!(matches!(
+            error,
+            Err(BuildStreamError::StreamConfigNotSupported)
+        ));
+    }
+}
@@ -11,14 +11,51 @@
 //! * Output is low-pass and high-pass filtered and clamped to 16-bit range.
 //! * Supports mono, stereo, and multi-channel synthesis.
 //!
+//! # Optional features
+//!
+//! * `wav`: the `wav` module, for writing output to WAV files.
+//! * `cpal`: the `cpal_output` module, for playing output through [cpal].
+//! * `testing`: the `testing` module, for golden-file tests of sound synthesis.
+//!
 //! # Based upon
 //!
-//! This library is a very thin wrapper on the original C library, found here: https://code.google.com/p/blip-buf/
+//! This library is a very thin wrapper on the original C library, found here: <https://code.google.com/p/blip-buf/>
 //!
 //! [BLEP]: http://www.cs.cmu.edu/~eli/L/icmc01/hardsync.html
+//! [cpal]: https://docs.rs/cpal
 
 #![warn(missing_docs)]
 
+use std::ops::Range;
+
+mod accumulator;
+#[cfg(feature = "cpal")]
+pub mod cpal_output;
+mod mixer;
+mod output;
+mod rate;
+mod rewind;
+mod split;
+mod stats;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod turbo;
+#[cfg(feature = "wav")]
+pub mod wav;
+
+use output::Output;
+use stats::StatsCollector;
+use turbo::Turbo;
+
+pub use accumulator::Accumulator;
+pub use mixer::Mixer;
+pub use output::OutputStage;
+pub use rate::RateController;
+pub use rewind::RewindBuffer;
+pub use split::{Consumer, Producer};
+pub use stats::{Stats, StatsWindow};
+pub use turbo::TurboMode;
+
 /// Maximum `clock_rate / sample_rate ratio`. For a given `sample_rate`,
 /// `clock_rate` must not be greater than `sample_rate * MAX_RATIO`.
 pub const MAX_RATIO: u64 = 1 << 20;
@@ -26,15 +63,22 @@ pub const MAX_RATIO: u64 = 1 << 20;
 /// Maximum number of samples that can be generated from one time frame.
 pub const MAX_FRAME: u64 = 4000;
 
+/// Largest delta magnitude a buffer with the default `i32` accumulator accepts,
+/// so that no tap of its band-limited step overflows. See `Accumulator`.
+pub const MAX_DELTA: i32 = i32::MAX / MAX_STEP;
+
+/// A slope of one amplitude unit per clock, as passed to `add_slope_change()`.
+/// Slopes are 16.16 fixed-point values. A slope change may alter the output by
+/// less than 65536 amplitude units per output sample, that is `delta_slope /
+/// SLOPE_UNIT * clock_rate / sample_rate`, which also keeps it within `MAX_DELTA`.
+pub const SLOPE_UNIT: i32 = 1 << 16;
+
 #[allow(non_camel_case_types)]
 type fixed_t = u64;
 
 #[allow(non_camel_case_types)]
 type enum_t = usize;
 
-#[allow(non_camel_case_types)]
-type buf_t = i32;
-
 const PRE_SHIFT: enum_t = 32;
 const TIME_BITS: enum_t = PRE_SHIFT + 20;
 const TIME_UNIT: fixed_t = (1 as fixed_t) << TIME_BITS;
@@ -43,36 +87,123 @@ const END_FRAME_EXTRA: enum_t = 2;
 
 const HALF_WIDTH: enum_t = 8;
 const BUF_EXTRA: enum_t = HALF_WIDTH * 2 + END_FRAME_EXTRA;
+/* Synthesis delays a step by this long, to where it crosses halfway */
+const STEP_DELAY: fixed_t = HALF_WIDTH as fixed_t * TIME_UNIT - TIME_UNIT / 2;
 const PHASE_BITS: enum_t = 5;
 const PHASE_COUNT: enum_t = 1 << PHASE_BITS;
 const DELTA_BITS: enum_t = 15;
 const DELTA_UNIT: enum_t = 1 << DELTA_BITS;
 const FRAC_BITS: enum_t = TIME_BITS - PRE_SHIFT;
+const SLOPE_BITS: enum_t = 16;
+const RAMP_BITS: enum_t = DELTA_BITS;
+/* Largest tap of BL_STEP */
+const MAX_STEP: i32 = 21022;
 
-/// Sample buffer that resamples from input clock rate to output sample rate
-pub struct BlipBuf {
+/// Sample buffer that resamples from input clock rate to output sample rate.
+/// Deltas are summed into `T`, see `Accumulator`.
+pub struct BlipBuf<T: Accumulator = i32> {
     factor: fixed_t,
+    /* Factor set by set_rates(), before turbo mode scales it */
+    nominal_factor: fixed_t,
+    clock_rate: f64,
     offset: fixed_t,
-    integrator: i32,
+    integrator: Integrator,
     avail: usize,
-    samples: Vec<buf_t>,
+    /* Clock domains handed out by clock_domain(), indexed by ClockDomain */
+    domains: Vec<Domain>,
+    samples: Vec<T>,
+    /* Length `samples` may grow to, see set_max_capacity() */
+    max_len: usize,
+    /* Slope changes, allocated on first use of add_slope_change(). These are
+    integrated twice: once into the integrator's slope, then into the output. */
+    ramps: Vec<i64>,
+    output: Output,
+    /* Unclamped samples, for output stages that look ahead */
+    scratch: Vec<i32>,
+    stats: Option<Box<StatsCollector>>,
+    turbo: Turbo,
+    hash: u64,
 }
 
-unsafe impl Send for BlipBuf {}
+/* State carried from one output sample to the next */
+#[derive(Clone, Copy, Default)]
+struct Integrator {
+    sum: i64,
+    slope: i64,
+    slope_rem: i64,
+}
+
+impl Integrator {
+    /// Returns the current output sample, before clamping, then advances past
+    /// `delta` and high-pass filters.
+    #[inline]
+    fn next(&mut self, delta: i64) -> i32 {
+        let raw = (self.sum >> DELTA_BITS).clamp(i32::MIN.into(), i32::MAX.into()) as i32;
+        let s = clamp_to_i16(raw);
+        self.sum = self.sum.saturating_add(delta);
+        self.sum -= (s << (DELTA_BITS - BASS_SHIFT)) as i64;
+        raw
+    }
+
+    /// Same as `next()`, but also advances past a slope change.
+    #[inline]
+    fn next_ramped(&mut self, delta: i64, ramp: i64) -> i32 {
+        self.slope += ramp;
+        self.slope_rem += self.slope;
+        let delta = delta.saturating_add(self.slope_rem >> RAMP_BITS);
+        self.slope_rem &= (1 << RAMP_BITS) - 1;
+        self.next(delta)
+    }
+
+    /// Runs over `samples` and, unless empty, the matching `ramps`, passing each
+    /// unclamped output sample to `f`.
+    #[inline]
+    fn run<T: Accumulator>(&mut self, samples: &[T], ramps: &[i64], mut f: impl FnMut(usize, i32)) {
+        if ramps.is_empty() {
+            for (i, &delta) in samples.iter().enumerate() {
+                f(i, self.next(delta.into()));
+            }
+        } else {
+            for (i, (&delta, &ramp)) in samples.iter().zip(ramps).enumerate() {
+                f(i, self.next_ramped(delta.into(), ramp));
+            }
+        }
+    }
+}
+
+unsafe impl<T: Accumulator> Send for BlipBuf<T> {}
 
 impl BlipBuf {
     /// Creates new buffer that can hold at most sample_count samples. Sets rates
     /// so that there are `MAX_RATIO` clocks per sample. Returns pointer to new
     /// buffer, or panics if insufficient memory.
     pub fn new(sample_count: u32) -> Self {
+        Self::with_accumulator(sample_count)
+    }
+}
+
+impl<T: Accumulator> BlipBuf<T> {
+    /// Same as `new()`, but sums deltas into `T`, for example
+    /// `BlipBuf::<i64>::with_accumulator()` to mix chips at high amplitude.
+    pub fn with_accumulator(sample_count: u32) -> Self {
         let sample_count = sample_count as usize;
         const FACTOR: u64 = TIME_UNIT / MAX_RATIO;
         Self {
             factor: FACTOR,
+            nominal_factor: FACTOR,
+            clock_rate: MAX_RATIO as f64,
             offset: FACTOR / 2,
-            integrator: 0,
+            integrator: Integrator::default(),
             avail: 0,
-            samples: vec![0; sample_count + BUF_EXTRA],
+            domains: Vec::new(),
+            samples: vec![T::default(); sample_count + BUF_EXTRA],
+            max_len: sample_count + BUF_EXTRA,
+            ramps: Vec::new(),
+            output: Output::new(),
+            scratch: Vec::new(),
+            stats: None,
+            turbo: Turbo::new(),
+            hash: HASH_START,
         }
     }
 
@@ -80,20 +211,23 @@ impl BlipBuf {
     /// `clock_rate` input clocks, approximately `sample_rate` samples are generated.
     /// Returns an error if clock_rate exceeds maximum, relative to sample_rate (the ratio sample_rate / clock_rate isn't between 0 and 1)
     pub fn set_rates(&mut self, clock_rate: f64, sample_rate: f64) -> Result<(), &'static str> {
-        let factor: f64 = (TIME_UNIT as f64) * sample_rate / clock_rate;
-        let factor_int = factor as fixed_t;
-
-        /* Fails if clock_rate exceeds maximum, relative to sample_rate */
-        let in_bounds = 0.0 <= factor - factor_int as f64 && factor - (factor_int as f64) < 1.0;
-        if !in_bounds {
-            return Err("clock_rate exceeds maximum, relative to sample_rate");
-        }
+        self.nominal_factor = rate_factor(clock_rate, sample_rate)?;
+        self.factor = self.turbo.factor(self.nominal_factor);
+        self.clock_rate = clock_rate;
+        Ok(())
+    }
 
-        self.factor = factor.ceil() as fixed_t;
+    /// Input clock rate last passed to `set_rates()`, or `MAX_RATIO` if it
+    /// hasn't been called.
+    pub fn clock_rate(&self) -> f64 {
+        self.clock_rate
+    }
 
-        /* At this point, factor is most likely rounded up, but could still
-        have been rounded down in the floating-point calculation. */
-        Ok(())
+    /// Output sample rate, reconstructed from the clock rate and the ratio
+    /// `set_rates()` derived from both. This may differ from the sample rate
+    /// passed there in the last few digits, and is 1.0 if it hasn't been called.
+    pub fn sample_rate(&self) -> f64 {
+        self.clock_rate * self.nominal_factor as f64 / TIME_UNIT as f64
     }
 
     /// Clears entire buffer. Afterwards, `samples_avail() == 0`.
@@ -105,18 +239,123 @@ impl BlipBuf {
         a 64-bit factor this is years, the halving isn't a problem. */
 
         self.offset = self.factor / 2;
+        for domain in &mut self.domains {
+            domain.offset = self.offset;
+        }
         self.avail = 0;
-        self.integrator = 0;
-        self.samples.fill(0);
+        self.integrator = Integrator::default();
+        self.samples.fill(T::default());
+        self.ramps.fill(0);
+        self.output.reset();
     }
 
     /// Adds positive/negative delta into buffer at specified clock time.
-    /// Returns an error if clock_time exceeds the buffer's capacity
+    /// Returns an error if clock_time exceeds the buffer's capacity, or if the
+    /// magnitude of delta exceeds `Accumulator::MAX_DELTA`
     pub fn add_delta(&mut self, clock_time: u32, delta: i32) -> Result<(), &'static str> {
-        let time = clock_time as fixed_t;
-        let fixed = ((time * self.factor + self.offset) >> PRE_SHIFT) as usize;
-        let out_index = self.avail + (fixed >> FRAC_BITS);
-        if out_index + 16 > self.samples.len() {
+        let fixed = clock_offset(clock_time, self.factor, self.offset)? >> PRE_SHIFT;
+        self.add_delta_fixed(self.avail, fixed as usize, delta)
+    }
+
+    /// Same as `add_delta()`, but at `frac / 65536` clocks past `clock_time`. Useful
+    /// for channels that change on a fraction of the input clock, for example every
+    /// 3.5 clocks.
+    /// Returns an error if clock_time exceeds the buffer's capacity, or if the
+    /// magnitude of delta exceeds `Accumulator::MAX_DELTA`
+    pub fn add_delta_fractional(
+        &mut self,
+        clock_time: u32,
+        frac: u16,
+        delta: i32,
+    ) -> Result<(), &'static str> {
+        let frac_time = ((frac as u128 * self.factor as u128) >> 16) as fixed_t;
+        let fixed = clock_offset(clock_time, self.factor, self.offset + frac_time)? >> PRE_SHIFT;
+        self.add_delta_fixed(self.avail, fixed as usize, delta)
+    }
+
+    /// Creates a clock domain with its own input clock rate, whose clock time 0
+    /// is the start of the current time frame. See `ClockDomain` for details.
+    /// Returns an error if clock_rate exceeds maximum, relative to sample_rate
+    pub fn clock_domain(
+        &mut self,
+        clock_rate: f64,
+        sample_rate: f64,
+    ) -> Result<ClockDomain, &'static str> {
+        self.domains.push(Domain {
+            factor: rate_factor(clock_rate, sample_rate)?,
+            offset: self.offset,
+        });
+        Ok(ClockDomain(self.domains.len() - 1))
+    }
+
+    /// Sets approximate clock rate of `domain`, and output sample rate of this
+    /// buffer. Works like `set_rates()`.
+    /// Returns an error if clock_rate exceeds maximum, relative to sample_rate,
+    /// or if domain wasn't created by this buffer
+    pub fn set_domain_rates(
+        &mut self,
+        domain: ClockDomain,
+        clock_rate: f64,
+        sample_rate: f64,
+    ) -> Result<(), &'static str> {
+        let factor = rate_factor(clock_rate, sample_rate)?;
+        self.domain_mut(domain)?.factor = factor;
+        Ok(())
+    }
+
+    /// Number of clocks of `domain` in the current time frame if it is ended at
+    /// `clock_duration`: `end_frame(clock_duration)` begins the domain's next
+    /// frame at this clock time. Run the chip in that domain up to here before
+    /// ending the frame.
+    /// Returns an error if clock_duration exceeds the buffer's capacity, or if
+    /// domain wasn't created by this buffer
+    pub fn domain_clocks(
+        &self,
+        domain: ClockDomain,
+        clock_duration: u32,
+    ) -> Result<u32, &'static str> {
+        let end = clock_offset(clock_duration, self.factor, self.offset)?;
+        let clocks = self.domain(domain)?.clocks_until(end);
+        u32::try_from(clocks).map_err(|_| "buffer size was exceeded")
+    }
+
+    /// Same as `add_delta()`, but with `clock_time` in the clock units of
+    /// `domain`, relative to the start of its current time frame.
+    /// Returns an error if clock_time exceeds the buffer's capacity, if the
+    /// magnitude of delta exceeds `Accumulator::MAX_DELTA`, or if domain wasn't
+    /// created by this buffer
+    pub fn add_delta_in(
+        &mut self,
+        domain: ClockDomain,
+        clock_time: u32,
+        delta: i32,
+    ) -> Result<(), &'static str> {
+        let domain = *self.domain(domain)?;
+        let fixed = clock_offset(clock_time, domain.factor, domain.offset)? >> PRE_SHIFT;
+        self.add_delta_fixed(self.avail, fixed as usize, delta)
+    }
+
+    fn domain(&self, domain: ClockDomain) -> Result<&Domain, &'static str> {
+        self.domains
+            .get(domain.0)
+            .ok_or("clock domain belongs to another buffer")
+    }
+
+    fn domain_mut(&mut self, domain: ClockDomain) -> Result<&mut Domain, &'static str> {
+        self.domains
+            .get_mut(domain.0)
+            .ok_or("clock domain belongs to another buffer")
+    }
+
+    fn add_delta_fixed(
+        &mut self,
+        base: usize,
+        fixed: usize,
+        delta: i32,
+    ) -> Result<(), &'static str> {
+        let delta = check_delta::<T>(delta)?;
+        let out_index = base + (fixed >> FRAC_BITS);
+        if !self.reserve(out_index + 16) {
             return Err("buffer size was exceeded");
         }
 
@@ -124,57 +363,132 @@ impl BlipBuf {
         let phase = fixed >> PHASE_SHIFT & (PHASE_COUNT - 1);
         let phase_rev = PHASE_COUNT - phase;
 
-        let interp = (fixed >> (PHASE_SHIFT - DELTA_BITS) & (DELTA_UNIT - 1)) as i32;
-        let delta2 = (delta * interp) >> DELTA_BITS;
-        let delta1 = delta - delta2;
+        let interp = (fixed >> (PHASE_SHIFT - DELTA_BITS) & (DELTA_UNIT - 1)) as i64;
+        let delta2 = ((delta as i64 * interp) >> DELTA_BITS) as i32;
+        let (delta1, delta2) = (T::from(delta - delta2), T::from(delta2));
 
+        /* With delta checked, every tap fits in T, and only the sums can overflow */
+        let step = |phase: usize, i: usize| T::from(BL_STEP[phase][i]);
+        let out = &mut self.samples[out_index..out_index + 16];
         for i in 0..8 {
-            self.samples[out_index + i] +=
-                BL_STEP[phase][i] * delta1 + BL_STEP[phase + 1][i] * delta2;
-        }
-        for i in 0..8 {
-            self.samples[out_index + 8 + i] +=
-                BL_STEP[phase_rev][7 - i] * delta1 + BL_STEP[phase_rev - 1][7 - i] * delta2;
+            let a = step(phase, i) * delta1 + step(phase + 1, i) * delta2;
+            let b = step(phase_rev, 7 - i) * delta1 + step(phase_rev - 1, 7 - i) * delta2;
+            out[i] = out[i].saturating_add(a);
+            out[8 + i] = out[8 + i].saturating_add(b);
         }
 
         Ok(())
     }
 
-    /// Same as `add_delta()`, but uses faster, lower-quality synthesis.
-    /// Returns an error if clock_time exceeds the buffer's capacity
+    /// Same as `add_delta()`, but uses faster, lower-quality synthesis. Its step
+    /// is spread over only two samples, so with the default `i32` accumulator,
+    /// deltas beyond ±65535 saturate.
+    /// Returns an error if clock_time exceeds the buffer's capacity, or if the
+    /// magnitude of delta exceeds `Accumulator::MAX_DELTA`
     pub fn add_delta_fast(&mut self, clock_time: u32, delta: i32) -> Result<(), &'static str> {
-        let time = clock_time as fixed_t;
-        let fixed = ((time * self.factor + self.offset) >> PRE_SHIFT) as usize;
+        let fixed = (clock_offset(clock_time, self.factor, self.offset)? >> PRE_SHIFT) as usize;
+        let delta = check_delta::<T>(delta)?;
+
+        let out_index = self.avail + (fixed >> FRAC_BITS);
+        if !self.reserve(out_index + 16) {
+            return Err("buffer size was exceeded");
+        }
+
+        /* Unlike taps of the full step, these can exceed 32 bits */
+        let interp = (fixed >> (FRAC_BITS - DELTA_BITS) & (DELTA_UNIT - 1)) as i64;
+        let delta = delta as i64;
+        let delta2 = T::saturate(delta * interp);
+        let delta1 = T::saturate(delta * (DELTA_UNIT as i64) - delta * interp);
+
+        let out = &mut self.samples[out_index + 7..out_index + 9];
+        out[0] = out[0].saturating_add(delta1);
+        out[1] = out[1].saturating_add(delta2);
+
+        Ok(())
+    }
+
+    /// Changes the slope of the waveform by `delta_slope` at specified clock time,
+    /// using band-limited ramps. Slopes are in amplitude units per clock, as
+    /// 16.16 fixed-point values (see `SLOPE_UNIT`), and stay in effect until
+    /// changed again, so a triangle wave needs only two slope changes per period.
+    /// Returns an error if clock_time exceeds the buffer's capacity, or if
+    /// delta_slope changes the output by too much per sample (see `SLOPE_UNIT`)
+    pub fn add_slope_change(
+        &mut self,
+        clock_time: u32,
+        delta_slope: i32,
+    ) -> Result<(), &'static str> {
+        /* Summing the ramp's per-sample increments puts it half a sample early
+        relative to the continuous waveform, so delay it by that much. */
+        let offset = self.offset + TIME_UNIT / 2;
+        let fixed = (clock_offset(clock_time, self.factor, offset)? >> PRE_SHIFT) as usize;
+
+        /* Convert to a change in output per sample, with RAMP_BITS of fraction.
+        Fails if that doesn't fit, or is more than a delta could change it by. */
+        let per_sample =
+            ((delta_slope as i128) << (TIME_BITS + RAMP_BITS - SLOPE_BITS)) / self.factor as i128;
+        let delta = i32::try_from(per_sample).map_err(|_| "slope change is too steep")?;
+        check_delta::<T>(delta >> RAMP_BITS)?;
+        let delta = delta as i64;
 
         let out_index = self.avail + (fixed >> FRAC_BITS);
-        if out_index + 8 > self.samples.len() {
+        if !self.reserve(out_index + 16) {
             return Err("buffer size was exceeded");
         }
 
-        let interp = (fixed >> (FRAC_BITS - DELTA_BITS) & (DELTA_UNIT - 1)) as i32;
-        let delta2 = delta * interp;
+        if self.ramps.is_empty() {
+            self.ramps = vec![0; self.samples.len()];
+        }
+
+        const PHASE_SHIFT: usize = FRAC_BITS - PHASE_BITS;
+        let phase = fixed >> PHASE_SHIFT & (PHASE_COUNT - 1);
+        let phase_rev = PHASE_COUNT - phase;
 
-        self.samples[out_index + 7] += delta * (DELTA_UNIT as i32) - delta2;
-        self.samples[out_index + 8] += delta2;
+        let interp = (fixed >> (PHASE_SHIFT - DELTA_BITS) & (DELTA_UNIT - 1)) as i64;
+        let delta2 = (delta * interp) >> DELTA_BITS;
+        let delta1 = delta - delta2;
+
+        /* Unlike a step, a slope persists, so the kernel's rounding error would
+        make the waveform drift. Fold it into the center tap. */
+        let mut total = 0;
+        let out = &mut self.ramps[out_index..out_index + 16];
+        for i in 0..8 {
+            let a = BL_STEP[phase][i] as i64 * delta1 + BL_STEP[phase + 1][i] as i64 * delta2;
+            let b = BL_STEP[phase_rev][7 - i] as i64 * delta1
+                + BL_STEP[phase_rev - 1][7 - i] as i64 * delta2;
+            out[i] += a;
+            out[8 + i] += b;
+            total += a + b;
+        }
+        out[8] += (delta << DELTA_BITS) - total;
 
         Ok(())
     }
 
     /// Length of time frame, in clocks, needed to make `sample_count` additional
     /// samples available.
-    /// Returns an error if sample_count exceeds the buffer's capacity
+    /// Returns an error if sample_count exceeds the buffer's capacity, or is more
+    /// than one time frame can generate (somewhat more than `MAX_FRAME`)
     pub fn clocks_needed(&self, sample_count: u32) -> Result<u32, &'static str> {
+        /* Samples the output stage still holds back have to come first */
+        let held = self.output.lookahead().saturating_sub(self.avail);
+        let sample_count = sample_count as usize + held;
+
         /* Fails if buffer can't hold that many more samples */
-        if self.avail + sample_count as usize > self.samples.len() {
+        let len = self.avail + sample_count + BUF_EXTRA;
+        if len > self.samples.len().max(self.max_len) {
             return Err("can't hold that many more samples");
         }
 
-        let needed = sample_count as fixed_t * TIME_UNIT;
+        /* Fails if end_frame() couldn't represent a frame that long */
+        let needed = (sample_count as fixed_t)
+            .checked_mul(TIME_UNIT)
+            .ok_or("can't generate that many samples in one time frame")?;
         if needed < self.offset {
             return Ok(0);
         }
 
-        let res = (needed - self.offset + self.factor - 1) / self.factor;
+        let res = (needed - self.offset).div_ceil(self.factor);
         Ok(res as u32)
     }
 
@@ -182,24 +496,119 @@ impl BlipBuf {
     /// samples. Also begins new time frame at `clock_duration`, so that clock time 0 in
     /// the new time frame specifies the same clock as `clock_duration` in the old time
     /// frame specified. Deltas can have been added slightly past `clock_duration` (up to
-    /// however many clocks there are in two output samples).
+    /// however many clocks there are in two output samples). Ends the frames of
+    /// all clock domains as well, see `domain_clocks()`.
     /// Returns an error if clock_duration exceeds the buffer's capacity
     pub fn end_frame(&mut self, clock_duration: u32) -> Result<(), &'static str> {
-        let off = (clock_duration as fixed_t) * self.factor + self.offset;
+        let off = clock_offset(clock_duration, self.factor, self.offset)?;
         let avail = self.avail + (off >> TIME_BITS) as usize;
-        if avail > self.samples.len() {
+        if !self.reserve(avail + BUF_EXTRA) {
             return Err("buffer size was exceeded");
         }
 
+        let start = self.avail;
         self.avail = avail;
         self.offset = off & (TIME_UNIT - 1);
+        for domain in &mut self.domains {
+            domain.end_frame(off, self.offset);
+        }
+        self.shorten_frame(start);
 
         Ok(())
     }
 
-    /// Number of buffered samples available for reading.
+    /// Lets buffer grow to hold up to `sample_count` samples, for when a time frame
+    /// turns out longer than expected, such as while fast-forwarding. Growing
+    /// keeps all samples and deltas already added. By default, the buffer never
+    /// grows beyond the `sample_count` passed to `new()`.
+    pub fn set_max_capacity(&mut self, sample_count: u32) {
+        self.max_len = sample_count as usize + BUF_EXTRA;
+    }
+
+    /* Grows buffer to at least `len` samples if allowed. Returns whether it is
+    now large enough. */
+    fn reserve(&mut self, len: usize) -> bool {
+        if len <= self.samples.len() {
+            return true;
+        }
+        if len > self.max_len {
+            return false;
+        }
+
+        let new_len = len.max(self.samples.len() * 2).min(self.max_len);
+        self.samples.resize(new_len, T::default());
+        if !self.ramps.is_empty() {
+            self.ramps.resize(new_len, 0);
+        }
+        true
+    }
+
+    /// Position where a delta added at `clock_time` in the current time frame
+    /// shows up in the output, in samples from the next sample read. This is
+    /// where the band-limited step crosses halfway, which synthesis delays by 7.5
+    /// samples, so it is later than where `end_frame(clock_time)` would end the
+    /// frame.
+    pub fn clock_to_sample(&self, clock_time: u32) -> f64 {
+        let time = clock_time as u128 * self.factor as u128 + self.offset as u128;
+        self.avail as f64 + (time + STEP_DELAY as u128) as f64 / TIME_UNIT as f64
+    }
+
+    /// Earliest clock time in the current time frame whose delta shows up at or
+    /// after `sample_index`, counted from the next sample read. The inverse of
+    /// `clock_to_sample()`, rounded up. Returns 0 for samples before clock time 0.
+    pub fn sample_to_clock(&self, sample_index: u32) -> u64 {
+        let samples = (sample_index as usize).saturating_sub(self.avail) as u128;
+        let delay = self.offset as u128 + STEP_DELAY as u128;
+        let needed = (samples * TIME_UNIT as u128).saturating_sub(delay);
+        let clocks = needed.div_ceil(self.factor as u128);
+        clocks.min(u64::MAX as u128) as u64
+    }
+
+    /// Largest clock time `add_delta()` and `add_delta_fast()` currently accept,
+    /// given the samples already available and the capacity the buffer may grow
+    /// to.
+    pub fn max_clock_time(&self) -> u32 {
+        /* The step is written to the 16 samples from the one clock_time falls in */
+        let len = self.samples.len().max(self.max_len);
+        let last = len.saturating_sub(self.avail + 16) as u128;
+
+        /* The fixed-point time must also fit in 64 bits */
+        let end = ((last + 1) * TIME_UNIT as u128).min(1 << 64);
+        let clocks = (end - 1).saturating_sub(self.offset as u128) / self.factor as u128;
+        clocks.min(u32::MAX as u128) as u32
+    }
+
+    /// Number of buffered samples available for reading. With
+    /// `OutputStage::Limiter`, the last `lookahead` samples of ended time frames
+    /// aren't available until later frames are ended.
     pub fn samples_avail(&self) -> u32 {
-        self.avail as u32
+        self.readable() as u32
+    }
+
+    /* Samples the output stage can be applied to, which are those it has all
+    its lookahead for */
+    fn readable(&self) -> usize {
+        self.avail.saturating_sub(self.output.lookahead())
+    }
+
+    /// Number of samples the buffer can hold, which is the `sample_count` passed
+    /// to `new()`, or to `set_max_capacity()` if larger.
+    pub fn capacity(&self) -> u32 {
+        (self.samples.len().max(self.max_len) - BUF_EXTRA) as u32
+    }
+
+    /// Number of additional samples time frames can make available before the
+    /// buffer is full. This is `capacity() - samples_avail()`, less any samples
+    /// `OutputStage::Limiter` holds back.
+    pub fn free_samples(&self) -> u32 {
+        self.capacity() - self.avail as u32
+    }
+
+    /// Number of input clocks of ended time frames that haven't been read as
+    /// output samples yet, which is how far output lags behind emulation.
+    pub fn pending_clocks(&self) -> u64 {
+        let time = self.avail as u128 * TIME_UNIT as u128 + self.offset as u128;
+        (time / self.factor as u128).min(u64::MAX as u128) as u64
     }
 
     fn remove_samples(&mut self, count: usize) {
@@ -210,7 +619,11 @@ impl BlipBuf {
         //    memmove( &buf [0], &buf [count], remain * sizeof buf [0] );
         //    memset( &buf [remain], 0, count * sizeof buf [0] );
         self.samples.copy_within(count..count + remain, 0);
-        self.samples[remain..].fill(0);
+        self.samples[remain..].fill(T::default());
+        if !self.ramps.is_empty() {
+            self.ramps.copy_within(count..count + remain, 0);
+            self.ramps[remain..].fill(0);
+        }
     }
 
     /// Reads and removes at most `buf.len()` samples and writes them to `buf`. If
@@ -219,64 +632,376 @@ impl BlipBuf {
     /// samples. Returns number of samples actually read.
     pub fn read_samples(&mut self, buf: &mut [i16], stereo: bool) -> usize {
         let step = if stereo { 2 } else { 1 };
-        let count = (buf.len() / step).min(self.avail);
+        self.read_strided(buf, step, 0)
+    }
+
+    /// Same as `read_samples()`, but writes output to every `stride`th element of
+    /// `buf`, starting at `offset`. This allows interleaving any number of
+    /// buffers into one multi-channel sample stream, with `stride` channels and
+    /// this buffer at channel `offset`. Only whole frames of `stride` elements are
+    /// written. Returns number of samples actually read.
+    /// Returns an error if `stride` is zero or `offset` isn't less than `stride`
+    pub fn read_samples_strided(
+        &mut self,
+        buf: &mut [i16],
+        stride: usize,
+        offset: usize,
+    ) -> Result<usize, &'static str> {
+        if offset >= stride {
+            return Err("offset must be less than stride");
+        }
+        Ok(self.read_strided(buf, stride, offset))
+    }
+
+    fn read_strided(&mut self, buf: &mut [i16], stride: usize, offset: usize) -> usize {
+        let count = (buf.len() / stride).min(self.readable());
 
+        self.output.clipped = 0;
         if count > 0 {
-            let mut sum = self.integrator;
+            let buf = &mut buf[offset..];
+            let mut output = self.output;
+            let mut scratch = std::mem::take(&mut self.scratch);
+            let mut hash = self.hash;
+            self.integrator = self.render(
+                self.integrator,
+                0..count,
+                &mut output,
+                &mut scratch,
+                |i, s| {
+                    buf[i * stride] = s;
+                    hash = hash_sample(hash, s);
+                },
+            );
+            self.output = output;
+            self.scratch = scratch;
+            self.hash = hash;
+            self.remove_samples(count);
+        }
 
-            for i in 0..count {
-                let s = clamp_to_i16(sum >> DELTA_BITS);
-                buf[i * step] = s as i16;
-                sum += self.samples[i];
-                sum -= s << (DELTA_BITS - BASS_SHIFT);
+        if let Some(stats) = &mut self.stats {
+            stats.begin_read();
+            for &s in buf.iter().skip(offset).step_by(stride).take(count) {
+                stats.add(s);
             }
+            stats.add_clamped(self.output.clipped);
+        }
 
-            self.integrator = sum;
-            self.remove_samples(count);
+        count
+    }
+
+    /// Sets how output samples are brought into 16-bit range. The default is
+    /// `OutputStage::HardClamp`. Applies to all reads except through `Mixer`,
+    /// which clamps its mix instead.
+    pub fn set_output_stage(&mut self, stage: OutputStage) {
+        self.output.stage = stage;
+    }
+
+    /// Current output stage.
+    pub fn output_stage(&self) -> OutputStage {
+        self.output.stage
+    }
+
+    /// Number of samples in the last read that exceeded 16-bit range before the
+    /// output stage was applied.
+    pub fn clipped_samples(&self) -> usize {
+        self.output.clipped
+    }
+
+    /// Starts collecting statistics about the level of samples read, over the
+    /// given window. Collection is disabled by default, as it costs a little time
+    /// on every read. Peeked and skipped samples aren't included, nor are reads
+    /// through `Mixer`.
+    pub fn enable_stats(&mut self, window: StatsWindow) {
+        self.stats = Some(Box::new(StatsCollector::new(window)));
+    }
+
+    /// Stops collecting statistics.
+    pub fn disable_stats(&mut self) {
+        self.stats = None;
+    }
+
+    /// Restarts statistics collection from zero samples, if enabled.
+    pub fn reset_stats(&mut self) {
+        if let Some(stats) = &mut self.stats {
+            stats.reset();
         }
+    }
+
+    /// Statistics collected so far, or `None` if collection isn't enabled.
+    pub fn stats(&self) -> Option<Stats> {
+        self.stats.as_ref().map(|stats| stats.stats())
+    }
 
+    /// Hash of all samples read so far, for cheaply checking that two buffers
+    /// produced the same output, such as on netplay peers or against a golden
+    /// value in tests. Covers every read except through `Mixer`; peeked and
+    /// skipped samples aren't included. The hash is 64-bit FNV-1a over the
+    /// samples' little-endian bytes, so it doesn't depend on the platform.
+    pub fn output_hash(&self) -> u64 {
+        self.hash
+    }
+
+    /// Restarts `output_hash()` as if no samples had been read.
+    pub fn reset_output_hash(&mut self) {
+        self.hash = HASH_START;
+    }
+
+    /// Same as `read_samples()` with `stereo` false, but leaves the samples in the
+    /// buffer, so the next read returns them again. Returns number of samples
+    /// written to `buf`.
+    pub fn peek_samples(&self, buf: &mut [i16]) -> usize {
+        let count = buf.len().min(self.readable());
+        let mut output = self.output;
+        self.render(
+            self.integrator,
+            0..count,
+            &mut output,
+            &mut Vec::new(),
+            |i, s| buf[i] = s,
+        );
         count
     }
+
+    /// Removes at most `count` samples without reading them. The filters still
+    /// run over the skipped samples, so the samples after them come out as if
+    /// they had been read. Returns number of samples actually skipped.
+    pub fn skip_samples(&mut self, count: usize) -> usize {
+        let count = count.min(self.readable());
+        if count > 0 {
+            self.integrator = self.integrate(count, |_, _| {});
+            self.remove_samples(count);
+        }
+        count
+    }
+
+    /// Reads and removes all available samples, appending them to `out`. Returns
+    /// number of samples read.
+    pub fn read_samples_to_vec(&mut self, out: &mut Vec<i16>) -> usize {
+        let start = out.len();
+        out.resize(start + self.readable(), 0);
+        self.read_samples(&mut out[start..], false)
+    }
+
+    /// Returns an iterator that reads and removes available samples one at a
+    /// time. Samples the iterator yielded are removed when it is dropped, so
+    /// stopping early leaves the rest in the buffer, ready for the next read.
+    pub fn drain(&mut self) -> Drain<'_, T> {
+        self.output.clipped = 0;
+        if let Some(stats) = &mut self.stats {
+            stats.begin_read();
+        }
+        Drain {
+            state: self.integrator,
+            output: self.output,
+            scratch: std::mem::take(&mut self.scratch),
+            blip: self,
+            pos: 0,
+        }
+    }
+
+    /* Runs the integrator over the first `count` samples without removing them,
+    passing each unclamped output sample to `f`. Returns the resulting state. */
+    #[inline]
+    fn integrate(&self, count: usize, f: impl FnMut(usize, i32)) -> Integrator {
+        let mut state = self.integrator;
+        state.run(&self.samples[..count], self.ramps(0..count), f);
+        state
+    }
+
+    /* Runs the integrator from `state` over `range` without removing samples, and
+    passes each output sample to `f` after the output stage. Returns the state
+    at the end of `range`. */
+    fn render(
+        &self,
+        mut state: Integrator,
+        range: Range<usize>,
+        output: &mut Output,
+        scratch: &mut Vec<i32>,
+        mut f: impl FnMut(usize, i16),
+    ) -> Integrator {
+        let samples = &self.samples[range.clone()];
+        let lookahead = output.lookahead();
+        if lookahead == 0 {
+            state.run(samples, self.ramps(range), |i, raw| f(i, output.shape(raw)));
+            return state;
+        }
+
+        let end = range.end + lookahead;
+        scratch.clear();
+        state.run(samples, self.ramps(range.clone()), |_, raw| {
+            scratch.push(raw)
+        });
+        let mut ahead = state;
+        let samples = &self.samples[range.end..end];
+        ahead.run(samples, self.ramps(range.end..end), |_, raw| {
+            scratch.push(raw)
+        });
+
+        for i in 0..range.len() {
+            let window = &scratch[i..scratch.len().min(i + lookahead + 1)];
+            f(i, output.limit(window));
+        }
+        state
+    }
+
+    fn ramps(&self, range: Range<usize>) -> &[i64] {
+        self.ramps.get(range).unwrap_or(&[])
+    }
 }
 
-#[inline]
-fn clamp_to_i16(n: i32) -> i32 {
-    n.clamp(i16::MIN.into(), i16::MAX.into())
+/// Iterator returned by `BlipBuf::drain()`.
+pub struct Drain<'a, T: Accumulator = i32> {
+    blip: &'a mut BlipBuf<T>,
+    state: Integrator,
+    output: Output,
+    scratch: Vec<i32>,
+    pos: usize,
 }
 
-#[cfg(test)]
-mod test {
-    use super::BlipBuf;
+impl<T: Accumulator> Iterator for Drain<'_, T> {
+    type Item = i16;
 
-    #[test]
-    fn basics() {
-        let mut blipbuf = BlipBuf::new(44100);
-        blipbuf.set_rates((1 << 22) as f64, 44100f64).unwrap();
-        drop(blipbuf);
+    fn next(&mut self) -> Option<i16> {
+        if self.pos >= self.blip.readable() {
+            return None;
+        }
+
+        let mut s = 0;
+        self.state = self.blip.render(
+            self.state,
+            self.pos..self.pos + 1,
+            &mut self.output,
+            &mut self.scratch,
+            |_, out| s = out,
+        );
+        self.pos += 1;
+        self.blip.hash = hash_sample(self.blip.hash, s);
+
+        if let Some(stats) = &mut self.blip.stats {
+            stats.add(s);
+        }
+
+        Some(s)
     }
 
-    #[test]
-    fn check_assumptions() {
-        use super::*;
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.blip.avail - self.pos;
+        (len, Some(len))
+    }
+}
 
-        const MAX_SAMPLE: i32 = i16::MAX as i32;
-        const MIN_SAMPLE: i32 = i16::MIN as i32;
-        let mut n: i32;
+impl<T: Accumulator> ExactSizeIterator for Drain<'_, T> {}
 
-        assert!((-3 >> 1) == -2); /* right shift must preserve sign */
-        n = MAX_SAMPLE * 2;
-        n = clamp_to_i16(n);
-        assert!(n == MAX_SAMPLE);
+impl<T: Accumulator> Drop for Drain<'_, T> {
+    fn drop(&mut self) {
+        self.blip.scratch = std::mem::take(&mut self.scratch);
+        if self.pos > 0 {
+            if let Some(stats) = &mut self.blip.stats {
+                stats.add_clamped(self.output.clipped);
+            }
+            self.blip.integrator = self.state;
+            self.blip.output = self.output;
+            self.blip.remove_samples(self.pos);
+        }
+    }
+}
 
-        n = MIN_SAMPLE * 2;
-        n = clamp_to_i16(n);
-        assert!(n == MIN_SAMPLE);
+fn rate_factor(clock_rate: f64, sample_rate: f64) -> Result<fixed_t, &'static str> {
+    let factor: f64 = (TIME_UNIT as f64) * sample_rate / clock_rate;
+    let factor_int = factor as fixed_t;
 
-        assert!(MAX_RATIO as fixed_t <= TIME_UNIT);
-        assert!(MAX_FRAME as fixed_t <= !1 >> TIME_BITS);
+    /* Fails if clock_rate exceeds maximum, relative to sample_rate */
+    let in_bounds = 0.0 <= factor - factor_int as f64 && factor - (factor_int as f64) < 1.0;
+    if !in_bounds {
+        return Err("clock_rate exceeds maximum, relative to sample_rate");
+    }
+
+    /* At this point, factor is most likely rounded up, but could still
+    have been rounded down in the floating-point calculation. */
+    Ok(factor.ceil() as fixed_t)
+}
+
+/// Handle to an input clock that runs independently of a `BlipBuf`'s own clock,
+/// for chips that aren't driven by the same oscillator but mix into the same
+/// output. Created by `BlipBuf::clock_domain()`, with its own clock rate; add
+/// deltas with `BlipBuf::add_delta_in()`, in clocks of the domain relative to
+/// the start of its current time frame.
+///
+/// Domain frames are ended by the buffer's `end_frame()`, at the first clock of
+/// the domain that isn't before the end of the buffer's frame. That clock,
+/// which `BlipBuf::domain_clocks()` tells in advance, becomes clock time 0 of
+/// the domain's next frame, so the two stay lined up however their rates relate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClockDomain(usize);
+
+/* Rate of a clock domain, and position of its clock time 0 in the same units as
+BlipBuf::offset */
+#[derive(Clone, Copy)]
+struct Domain {
+    factor: fixed_t,
+    offset: fixed_t,
+}
+
+impl Domain {
+    /* Number of clocks from clock time 0 to the first clock at or after `end` */
+    fn clocks_until(&self, end: fixed_t) -> u64 {
+        match (end.saturating_sub(self.offset), self.factor) {
+            (_, 0) => 0,
+            (time, factor) => time.div_ceil(factor),
+        }
+    }
+
+    /* Begins next frame at the first clock at or after `end`, given the offset
+    the buffer's next frame begins at */
+    fn end_frame(&mut self, end: fixed_t, offset: fixed_t) {
+        let past_end = match (end.checked_sub(self.offset), self.factor) {
+            (None, _) => self.offset - end,
+            (Some(_), 0) => 0,
+            (Some(time), factor) => (factor - time % factor) % factor,
+        };
+        self.offset = offset.saturating_add(past_end);
     }
 }
 
+const HASH_START: u64 = 0xcbf2_9ce4_8422_2325;
+
+#[inline]
+fn hash_sample(mut hash: u64, s: i16) -> u64 {
+    for byte in s.to_le_bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100_0000_01b3);
+    }
+    hash
+}
+
+/* Fixed-point time of `clock_time`, that is `clock_time * factor + offset`. Fails
+for clock times so far past the end of the buffer that this overflows. */
+#[inline]
+fn clock_offset(
+    clock_time: u32,
+    factor: fixed_t,
+    offset: fixed_t,
+) -> Result<fixed_t, &'static str> {
+    (clock_time as fixed_t)
+        .checked_mul(factor)
+        .and_then(|time| time.checked_add(offset))
+        .ok_or("buffer size was exceeded")
+}
+
+/* Fails if `delta` is too large for its step to fit in `T` */
+#[inline]
+fn check_delta<T: Accumulator>(delta: i32) -> Result<i32, &'static str> {
+    if delta.unsigned_abs() > T::MAX_DELTA as u32 {
+        return Err("delta exceeds MAX_DELTA");
+    }
+    Ok(delta)
+}
+
+#[inline]
+fn clamp_to_i16(n: i32) -> i32 {
+    n.clamp(i16::MIN.into(), i16::MAX.into())
+}
+
 const BL_STEP: &[[i32; 8]] = &[
     [43, -115, 350, -488, 1136, -914, 5861, 21022],
     [44, -118, 348, -473, 1076, -799, 5274, 21001],
@@ -312,3 +1037,467 @@ const BL_STEP: &[[i32; 8]] = &[
     [1, 40, -110, 350, -499, 1190, -1021, 6464],
     [0, 43, -115, 350, -488, 1136, -914, 5861],
 ];
+
+#[cfg(test)]
+mod test_util {
+    use super::BlipBuf;
+
+    /* Buffer of 4800 samples at 48000 Hz, clocked at `clock_rate` */
+    pub(crate) fn new_blip(clock_rate: f64) -> BlipBuf {
+        let mut blip = BlipBuf::new(4800);
+        blip.set_rates(clock_rate, 48000.0).unwrap();
+        blip
+    }
+
+    /* Adds a square wave that starts with a step of `delta` and flips every
+    `half_period` clocks until `duration`. Returns the step it would continue
+    with in the next frame. */
+    pub(crate) fn add_square_wave(
+        blip: &mut BlipBuf,
+        half_period: u32,
+        mut delta: i32,
+        duration: u32,
+    ) -> i32 {
+        for t in (0..duration).step_by(half_period as usize) {
+            blip.add_delta(t, delta).unwrap();
+            delta = -delta;
+        }
+        delta
+    }
+
+    /* One frame of 20000 clocks at 1 MHz, ended, with a square wave starting
+    with a step of `delta` and flipping every `half_period` clocks */
+    pub(crate) fn square_wave(half_period: u32, delta: i32) -> BlipBuf {
+        let mut blip = new_blip(1000000.0);
+        add_square_wave(&mut blip, half_period, delta, 20000);
+        blip.end_frame(20000).unwrap();
+        blip
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::test_util::{add_square_wave, new_blip};
+    use super::BlipBuf;
+
+    #[test]
+    fn basics() {
+        let mut blipbuf = BlipBuf::new(44100);
+        blipbuf.set_rates((1 << 22) as f64, 44100f64).unwrap();
+        drop(blipbuf);
+    }
+
+    #[test]
+    fn slope_changes_match_fine_steps() {
+        let mut ramps = new_blip(1000000.0);
+        let mut steps = new_blip(1000000.0);
+
+        /* Triangle wave, once with slope changes and once stepping every clock */
+        let slope = 8;
+        let mut rising = true;
+        for t in 0..40000 {
+            if t % 1000 == 0 {
+                rising = t == 0 || !rising;
+                let delta_slope = if t == 0 {
+                    slope
+                } else if rising {
+                    2 * slope
+                } else {
+                    -2 * slope
+                };
+                ramps
+                    .add_slope_change(t, delta_slope * super::SLOPE_UNIT)
+                    .unwrap();
+            }
+            steps
+                .add_delta(t, if rising { slope } else { -slope })
+                .unwrap();
+        }
+        ramps.end_frame(40000).unwrap();
+        steps.end_frame(40000).unwrap();
+
+        let a = &mut [0i16; 2000];
+        let b = &mut [0i16; 2000];
+        assert_eq!(ramps.read_samples(a, false), 1920);
+        assert_eq!(steps.read_samples(b, false), 1920);
+        for (x, y) in a.iter().zip(b.iter()) {
+            assert!((*x as i32 - *y as i32).abs() <= 16, "{} vs {}", x, y);
+        }
+    }
+
+    #[test]
+    fn steep_slope_change() {
+        use super::SLOPE_UNIT;
+
+        /* About 20.8 clocks per sample, so at most about 3145 amplitude units per clock */
+        let mut blip = new_blip(1000000.0);
+        blip.add_slope_change(0, 3100 * SLOPE_UNIT).unwrap();
+        blip.add_slope_change(0, -3100 * SLOPE_UNIT).unwrap();
+        assert!(blip.add_slope_change(0, 3200 * SLOPE_UNIT).is_err());
+        assert!(blip.add_slope_change(0, -3200 * SLOPE_UNIT).is_err());
+        assert!(blip.add_slope_change(0, i32::MIN).is_err());
+    }
+
+    #[test]
+    fn fractional_delta() {
+        /* Half a clock at 1MHz is exactly one clock at 2MHz */
+        let mut whole = new_blip(1000000.0);
+        let mut half = new_blip(2000000.0);
+        let mut frac = new_blip(1000000.0);
+        whole.add_delta(501, 10000).unwrap();
+        half.add_delta(1001, 10000).unwrap();
+        frac.add_delta_fractional(500, 0x8000, 10000).unwrap();
+        whole.end_frame(2000).unwrap();
+        half.end_frame(4000).unwrap();
+        frac.end_frame(2000).unwrap();
+
+        let a = &mut [0i16; 96];
+        let b = &mut [0i16; 96];
+        let c = &mut [0i16; 96];
+        whole.read_samples(a, false);
+        half.read_samples(b, false);
+        frac.read_samples(c, false);
+        assert_eq!(b, c);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn clock_domain_matches_own_rate() {
+        let mut shared = new_blip(1000000.0);
+        let mut native = new_blip(3579545.0);
+        let domain = shared.clock_domain(3579545.0, 48000.0).unwrap();
+
+        let (mut a, mut b) = (Vec::new(), Vec::new());
+        for frame in 0..600 {
+            let clocks = shared.domain_clocks(domain, 16667).unwrap();
+            let mut delta = 1000;
+            for t in (0..clocks).step_by(997 + frame % 10) {
+                shared.add_delta_in(domain, t, delta).unwrap();
+                native.add_delta(t, delta).unwrap();
+                delta = -delta;
+            }
+            native.end_frame(clocks).unwrap();
+            shared.end_frame(16667).unwrap();
+            shared.read_samples_to_vec(&mut a);
+            native.read_samples_to_vec(&mut b);
+        }
+
+        /* Frames line up to within a clock, so neither stream gets ahead */
+        assert!(a.len().abs_diff(b.len()) <= 1);
+        let count = a.len().min(b.len());
+        assert_eq!(a[..count], b[..count]);
+    }
+
+    #[test]
+    fn clock_domain_errors() {
+        let mut blip = new_blip(48000.0);
+        let domain = blip.clock_domain(48000.0, 48000.0).unwrap();
+        assert_eq!(blip.domain_clocks(domain, 4000), Ok(4000));
+        assert!(blip.domain_clocks(domain, 5000).is_err());
+        assert!(blip.add_delta_in(domain, 5000, 1).is_err());
+
+        let other = BlipBuf::new(4800).clock_domain(48000.0, 48000.0).unwrap();
+        assert_eq!(
+            BlipBuf::new(4800).add_delta_in(other, 0, 1),
+            Err("clock domain belongs to another buffer")
+        );
+    }
+
+    #[test]
+    fn clear_realigns_clock_domains() {
+        let mut blip = new_blip(1000000.0);
+        let domain = blip.clock_domain(3579545.0, 48000.0).unwrap();
+        blip.end_frame(1234).unwrap();
+        blip.clear();
+
+        let mut fresh = new_blip(1000000.0);
+        fresh.clear();
+        let fresh_domain = fresh.clock_domain(3579545.0, 48000.0).unwrap();
+        let (mut a, mut b) = (Vec::new(), Vec::new());
+        for (blip, domain, out) in [
+            (&mut blip, domain, &mut a),
+            (&mut fresh, fresh_domain, &mut b),
+        ] {
+            blip.add_delta_in(domain, 5000, 10000).unwrap();
+            blip.end_frame(20000).unwrap();
+            blip.read_samples_to_vec(out);
+        }
+        assert_eq!(a, b);
+    }
+
+    fn ramped_square_wave() -> BlipBuf {
+        let mut blip = new_blip(1000000.0);
+        add_square_wave(&mut blip, 300, 8000, 20000);
+        blip.add_slope_change(10000, 4 * super::SLOPE_UNIT).unwrap();
+        blip.end_frame(20000).unwrap();
+        blip
+    }
+
+    #[test]
+    fn drain_matches_read_samples() {
+        let mut read = ramped_square_wave();
+        let mut drained = ramped_square_wave();
+        let avail = read.samples_avail() as usize;
+
+        let mut expected = vec![0i16; avail];
+        read.read_samples(&mut expected[..500], false);
+        read.read_samples(&mut expected[500..], false);
+
+        /* Stop half way, so the remainder comes from the updated state */
+        let mut out: Vec<i16> = drained.drain().take(500).collect();
+        assert_eq!(drained.samples_avail() as usize, avail - 500);
+        assert_eq!(drained.drain().len(), avail - 500);
+        assert_eq!(drained.read_samples_to_vec(&mut out), avail - 500);
+        assert_eq!(out, expected);
+        assert_eq!(drained.drain().next(), None);
+    }
+
+    #[test]
+    fn strided_read() {
+        let mut mono = ramped_square_wave();
+        let mut strided = ramped_square_wave();
+        let expected = &mut [0i16; 200];
+        mono.read_samples(expected, false);
+
+        let buf = &mut [-1i16; 6 * 200 + 5];
+        assert!(strided.read_samples_strided(buf, 0, 0).is_err());
+        assert!(strided.read_samples_strided(buf, 6, 6).is_err());
+        assert_eq!(strided.read_samples_strided(buf, 6, 4), Ok(200));
+        for (frame, &s) in buf.chunks(6).zip(expected.iter()) {
+            assert_eq!(frame, &[-1, -1, -1, -1, s, -1]);
+        }
+        assert_eq!(buf[1200..], [-1; 5]);
+    }
+
+    #[test]
+    fn peek_and_skip() {
+        let mut read = ramped_square_wave();
+        let mut peeked = ramped_square_wave();
+        let expected = &mut [0i16; 900];
+        read.read_samples(expected, false);
+
+        let buf = &mut [0i16; 900];
+        assert_eq!(peeked.peek_samples(&mut buf[..300]), 300);
+        assert_eq!(peeked.peek_samples(&mut buf[..300]), 300);
+        assert_eq!(peeked.samples_avail(), read.samples_avail() + 900);
+        assert_eq!(peeked.skip_samples(200), 200);
+        assert_eq!(peeked.read_samples(&mut buf[200..], false), 700);
+        assert_eq!(buf[..], expected[..]);
+        assert_eq!(peeked.skip_samples(10000), read.samples_avail() as usize);
+    }
+
+    #[test]
+    fn output_hash() {
+        let mut a = ramped_square_wave();
+        let mut b = ramped_square_wave();
+        let start = a.output_hash();
+        a.read_samples(&mut [0i16; 500], false);
+        a.read_samples(&mut [0i16; 2000], false);
+
+        let mut drain = b.drain();
+        drain.by_ref().take(100).count();
+        drop(drain);
+        b.read_samples_strided(&mut [0i16; 3000], 3, 1).unwrap();
+        assert_eq!(a.output_hash(), b.output_hash());
+        assert_ne!(a.output_hash(), start);
+
+        let mut c = ramped_square_wave();
+        c.read_samples(&mut [0i16; 500], false);
+        assert_ne!(c.output_hash(), a.output_hash());
+        c.reset_output_hash();
+        assert_eq!(c.output_hash(), start);
+    }
+
+    #[test]
+    fn growth() {
+        let mut fixed = new_blip(1000000.0);
+        let mut growing = BlipBuf::new(1000);
+        growing.set_rates(1000000.0, 48000.0).unwrap();
+
+        fixed.add_delta(100, 10000).unwrap();
+        growing.add_delta(100, 10000).unwrap();
+        assert!(growing.add_delta(50000, 10000).is_err());
+        assert!(growing.clocks_needed(2000).is_err());
+        growing.set_max_capacity(4000);
+        assert!(growing.clocks_needed(2000).is_ok());
+        assert!(growing.add_delta_fast(84000, 10000).is_err());
+
+        for blip in [&mut fixed, &mut growing] {
+            blip.add_delta(50000, -10000).unwrap();
+            blip.add_slope_change(60000, super::SLOPE_UNIT).unwrap();
+            blip.end_frame(80000).unwrap();
+        }
+        assert!(growing.end_frame(4000).is_err());
+
+        let a = &mut [0i16; 4000];
+        let b = &mut [0i16; 4000];
+        assert_eq!(fixed.read_samples(a, false), 3840);
+        assert_eq!(growing.read_samples(b, false), 3840);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn wide_accumulator() {
+        let mut narrow = new_blip(1000000.0);
+        let mut wide = BlipBuf::<i64>::with_accumulator(4800);
+        wide.set_rates(1000000.0, 48000.0).unwrap();
+
+        narrow.add_delta(100, 20000).unwrap();
+        wide.add_delta(100, 20000).unwrap();
+        narrow.add_delta_fast(5000, -30000).unwrap();
+        wide.add_delta_fast(5000, -30000).unwrap();
+        narrow.end_frame(20000).unwrap();
+        wide.end_frame(20000).unwrap();
+
+        let a = &mut [0i16; 960];
+        let b = &mut [0i16; 960];
+        assert_eq!(narrow.read_samples(a, false), 960);
+        assert_eq!(wide.read_samples(b, false), 960);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn large_deltas() {
+        let mut narrow = new_blip(48000.0);
+        let mut wide = BlipBuf::<i64>::with_accumulator(4800);
+        let mut single = BlipBuf::<i64>::with_accumulator(4800);
+        wide.set_rates(48000.0, 48000.0).unwrap();
+        single.set_rates(48000.0, 48000.0).unwrap();
+        assert!(narrow.add_delta(0, super::MAX_DELTA + 1).is_err());
+        assert!(narrow.add_delta_fast(0, i32::MIN).is_err());
+        narrow.add_delta_fast(0, -super::MAX_DELTA).unwrap();
+        narrow.add_delta(0, 80000).unwrap();
+        narrow.add_delta(0, -80000).unwrap();
+        narrow.add_delta_fast(0, super::MAX_DELTA).unwrap();
+
+        /* Summing these overflows 32 bits, which only distorts the output */
+        let quarter = super::MAX_DELTA * 16384;
+        for _ in 0..65536 {
+            narrow.add_delta(0, super::MAX_DELTA).unwrap();
+            wide.add_delta(0, super::MAX_DELTA).unwrap();
+        }
+        for _ in 0..4 {
+            single.add_delta(0, quarter).unwrap();
+            wide.add_delta(400, -quarter).unwrap();
+            single.add_delta(400, -quarter).unwrap();
+        }
+
+        let mut out = [[0i16; 800]; 2];
+        narrow.end_frame(800).unwrap();
+        narrow.read_samples(&mut out[0], false);
+        for (blip, out) in [wide, single].iter_mut().zip(&mut out) {
+            blip.end_frame(800).unwrap();
+            assert_eq!(blip.read_samples(out, false), 800);
+        }
+        assert_eq!(out[0], out[1]);
+        assert_eq!(out[0][200], i16::MAX);
+    }
+
+    #[test]
+    fn clock_sample_conversion() {
+        let mut blip = new_blip(1000000.0);
+        blip.end_frame(12345).unwrap();
+        let avail = blip.samples_avail();
+
+        blip.add_delta(10000, 20000).unwrap();
+        let pos = blip.clock_to_sample(10000);
+        for n in [8, 9, 100, 959] {
+            let index = avail + n;
+            let clocks = blip.sample_to_clock(index);
+            assert!(blip.clock_to_sample(clocks as u32) >= index as f64);
+            assert!(clocks == 0 || blip.clock_to_sample(clocks as u32 - 1) < index as f64);
+        }
+        assert_eq!(blip.sample_to_clock(0), 0);
+
+        /* The step crosses halfway where clock_to_sample() says */
+        blip.end_frame(20000).unwrap();
+        let out = &mut [0i16; 1600];
+        let count = blip.read_samples(out, false);
+        let i = out[..count].iter().position(|&s| s >= 10000).unwrap();
+        let (a, b) = (out[i - 1] as f64, out[i] as f64);
+        let crossing = (i - 1) as f64 + (10000.0 - a) / (b - a);
+        assert!((crossing - pos).abs() < 0.1, "{crossing} vs {pos}");
+    }
+
+    #[test]
+    fn introspection() {
+        let mut blip = BlipBuf::new(4000);
+        assert_eq!(blip.clock_rate(), super::MAX_RATIO as f64);
+        assert_eq!(blip.sample_rate(), 1.0);
+
+        blip.set_rates(1789773.0, 48000.0).unwrap();
+        assert_eq!(blip.clock_rate(), 1789773.0);
+        assert!((blip.sample_rate() - 48000.0).abs() < 1e-6);
+        blip.set_turbo(4, super::TurboMode::TimeCompress).unwrap();
+        assert!((blip.sample_rate() - 48000.0).abs() < 1e-6);
+        blip.set_turbo(1, super::TurboMode::TimeCompress).unwrap();
+
+        assert_eq!(blip.capacity(), 4000);
+        assert_eq!(blip.free_samples(), 4000);
+        assert_eq!(blip.pending_clocks(), 0);
+
+        blip.end_frame(29830).unwrap();
+        assert_eq!(blip.samples_avail(), 800);
+        assert_eq!(blip.free_samples(), 3200);
+        assert_eq!(blip.pending_clocks(), 29830);
+        blip.read_samples(&mut [0i16; 500], false);
+        assert!(blip.pending_clocks().abs_diff(29830 * 300 / 800) < 40);
+        assert!(blip.clocks_needed(blip.free_samples()).is_ok());
+        assert!(blip.clocks_needed(blip.free_samples() + 1).is_err());
+
+        blip.set_max_capacity(8000);
+        assert_eq!(blip.capacity(), 8000);
+        blip.set_max_capacity(100);
+        assert_eq!(blip.capacity(), 4000);
+    }
+
+    #[test]
+    fn max_clock_time() {
+        let mut blip = BlipBuf::new(1000);
+        blip.set_rates(1000000.0, 48000.0).unwrap();
+        blip.end_frame(5000).unwrap();
+
+        let max = blip.max_clock_time();
+        blip.add_delta(max, 100).unwrap();
+        blip.add_delta_fast(max, 100).unwrap();
+        assert!(blip.add_delta(max + 1, 100).is_err());
+        assert!(blip.add_delta_fast(max + 1, 100).is_err());
+
+        blip.set_max_capacity(2000);
+        assert!(blip.max_clock_time() > max);
+        blip.add_delta(blip.max_clock_time(), 100).unwrap();
+
+        /* Limited by the fixed-point time instead of the capacity */
+        blip.set_rates(48000.0, 48000.0).unwrap();
+        blip.set_max_capacity(100000);
+        let max = blip.max_clock_time();
+        blip.add_delta(max, 100).unwrap();
+        assert!(blip.add_delta(max + 1, 100).is_err());
+    }
+
+    #[test]
+    #[allow(clippy::assertions_on_constants)]
+    fn check_assumptions() {
+        use super::*;
+
+        const MAX_SAMPLE: i32 = i16::MAX as i32;
+        const MIN_SAMPLE: i32 = i16::MIN as i32;
+        let mut n: i32;
+
+        assert!((-3 >> 1) == -2); /* right shift must preserve sign */
+        n = MAX_SAMPLE * 2;
+        n = clamp_to_i16(n);
+        assert!(n == MAX_SAMPLE);
+
+        n = MIN_SAMPLE * 2;
+        n = clamp_to_i16(n);
+        assert!(n == MIN_SAMPLE);
+
+        assert!(MAX_RATIO as fixed_t <= TIME_UNIT);
+        assert!(MAX_FRAME as fixed_t <= !1 >> TIME_BITS);
+        assert_eq!(
+            BL_STEP.iter().flatten().map(|s| s.abs()).max(),
+            Some(MAX_STEP)
+        );
+    }
+}
@@ -0,0 +1,214 @@
+use crate::{clamp_to_i16, Accumulator, BlipBuf};
+
+const GAIN_BITS: u32 = 16;
+
+struct Source<T: Accumulator> {
+    blip: BlipBuf<T>,
+    gain: f32,
+    pan: f32,
+    left: i32,
+    right: i32,
+}
+
+impl<T: Accumulator> Source<T> {
+    fn update_gains(&mut self) {
+        let unit = (1 << GAIN_BITS) as f32;
+        self.left = (self.gain * (1.0 - self.pan).min(1.0) * unit).round() as i32;
+        self.right = (self.gain * (1.0 + self.pan).min(1.0) * unit).round() as i32;
+    }
+}
+
+/// Mixes several buffers into one stereo output stream. Each source has its
+/// own gain and pan, applied to its output before clamping, so sources can be
+/// combined without losing precision or clipping separately.
+pub struct Mixer<T: Accumulator = i32> {
+    sources: Vec<Source<T>>,
+    mix: Vec<i32>,
+}
+
+impl Mixer {
+    /// Creates mixer without any sources.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<T: Accumulator> Default for Mixer<T> {
+    fn default() -> Self {
+        Self {
+            sources: Vec::new(),
+            mix: Vec::new(),
+        }
+    }
+}
+
+impl<T: Accumulator> Mixer<T> {
+    /// Adds `blip` as a source with a gain of 1.0, panned to the center. Returns
+    /// index of the new source.
+    pub fn add_source(&mut self, blip: BlipBuf<T>) -> usize {
+        let mut source = Source {
+            blip,
+            gain: 1.0,
+            pan: 0.0,
+            left: 0,
+            right: 0,
+        };
+        source.update_gains();
+        self.sources.push(source);
+        self.sources.len() - 1
+    }
+
+    /// Number of sources.
+    pub fn source_count(&self) -> usize {
+        self.sources.len()
+    }
+
+    /// Buffer of source `index`.
+    pub fn source(&self, index: usize) -> &BlipBuf<T> {
+        &self.sources[index].blip
+    }
+
+    /// Buffer of source `index`, for adding deltas and ending frames.
+    pub fn source_mut(&mut self, index: usize) -> &mut BlipBuf<T> {
+        &mut self.sources[index].blip
+    }
+
+    /// Sets gain of source `index`, where 1.0 leaves its amplitude unchanged.
+    pub fn set_gain(&mut self, index: usize, gain: f32) {
+        let source = &mut self.sources[index];
+        source.gain = gain;
+        source.update_gains();
+    }
+
+    /// Sets pan of source `index`, from -1.0 (left only) through 0.0 (both
+    /// channels at full gain) to 1.0 (right only).
+    pub fn set_pan(&mut self, index: usize, pan: f32) {
+        let source = &mut self.sources[index];
+        source.pan = pan.clamp(-1.0, 1.0);
+        source.update_gains();
+    }
+
+    /// Removes all sources and returns their buffers, in order of addition.
+    pub fn into_sources(self) -> Vec<BlipBuf<T>> {
+        self.sources.into_iter().map(|s| s.blip).collect()
+    }
+
+    /// Number of stereo frames available for reading, which is the least number
+    /// of samples available in any source.
+    pub fn samples_avail(&self) -> u32 {
+        self.sources
+            .iter()
+            .map(|s| s.blip.samples_avail())
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// Reads and removes at most `buf.len() / 2` stereo frames from all sources,
+    /// and writes their mix to `buf` as interleaved left and right samples.
+    /// Outputs 16-bit signed samples. Returns number of frames actually read.
+    pub fn read_samples(&mut self, buf: &mut [i16]) -> usize {
+        let count = (buf.len() / 2).min(self.samples_avail() as usize);
+        if count == 0 {
+            return 0;
+        }
+
+        let mix = &mut self.mix;
+        mix.clear();
+        mix.resize(count * 2, 0);
+
+        for source in &mut self.sources {
+            let (left, right) = (source.left as i64, source.right as i64);
+            let blip = &mut source.blip;
+            blip.integrator = blip.integrate(count, |i, raw| {
+                let l = (raw as i64 * left) >> GAIN_BITS;
+                let r = (raw as i64 * right) >> GAIN_BITS;
+                mix[i * 2] = mix[i * 2].saturating_add(i32::saturate(l));
+                mix[i * 2 + 1] = mix[i * 2 + 1].saturating_add(i32::saturate(r));
+            });
+            blip.remove_samples(count);
+        }
+
+        for (out, &sum) in buf.iter_mut().zip(mix.iter()) {
+            *out = clamp_to_i16(sum) as i16;
+        }
+
+        count
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Mixer;
+    use crate::test_util::square_wave;
+    use crate::BlipBuf;
+
+    #[test]
+    fn unity_gain_matches_read_samples() {
+        let mut mixer = Mixer::new();
+        mixer.add_source(square_wave(300, 8000));
+        let expected = &mut [0i16; 500];
+        square_wave(300, 8000).read_samples(expected, false);
+
+        let buf = &mut [0i16; 1000];
+        assert_eq!(mixer.read_samples(buf), 500);
+        for (frame, &s) in buf.chunks(2).zip(expected.iter()) {
+            assert_eq!(frame, &[s, s]);
+        }
+    }
+
+    #[test]
+    fn gain_and_pan() {
+        let mut mixer = Mixer::new();
+        let left = mixer.add_source(square_wave(300, 8000));
+        let right = mixer.add_source(square_wave(700, 8000));
+        mixer.set_pan(left, -1.0);
+        mixer.set_pan(right, 1.0);
+        mixer.set_gain(right, 0.5);
+        let expected_left = &mut [0i16; 500];
+        let expected_right = &mut [0i16; 500];
+        square_wave(300, 8000).read_samples(expected_left, false);
+        square_wave(700, 4000).read_samples(expected_right, false);
+
+        let buf = &mut [0i16; 1000];
+        assert_eq!(mixer.read_samples(buf), 500);
+        for (i, frame) in buf.chunks(2).enumerate() {
+            assert_eq!(frame[0], expected_left[i]);
+            assert!((frame[1] - expected_right[i]).abs() <= 1);
+        }
+        assert_eq!(mixer.samples_avail(), 460);
+    }
+
+    #[test]
+    fn clamps_after_mixing() {
+        /* Mixed before clamping, 45000 - 30000 doesn't clip */
+        let mut mixer = Mixer::new();
+        let loud = mixer.add_source(square_wave(2000, 30000));
+        mixer.add_source(square_wave(2000, -30000));
+        mixer.set_gain(loud, 1.5);
+        let buf = &mut [0i16; 1920];
+        assert_eq!(mixer.read_samples(buf), 960);
+        assert!(buf.iter().any(|&s| s > 14000));
+        assert!(buf.iter().all(|&s| s < 16000));
+
+        let mut mixer = Mixer::new();
+        mixer.add_source(square_wave(2000, 30000));
+        mixer.add_source(square_wave(2000, 30000));
+        assert_eq!(mixer.read_samples(buf), 960);
+        assert!(buf.contains(&i16::MAX));
+    }
+
+    #[test]
+    fn saturates_wide_sources() {
+        let mut blip = BlipBuf::<i64>::with_accumulator(4800);
+        blip.set_rates(1000000.0, 48000.0).unwrap();
+        blip.add_delta(0, i32::MAX).unwrap();
+        blip.end_frame(20000).unwrap();
+
+        let mut mixer = Mixer::default();
+        mixer.add_source(blip);
+        mixer.set_gain(0, 2.0);
+        let buf = &mut [0i16; 1920];
+        assert_eq!(mixer.read_samples(buf), 960);
+        assert!(buf[100..].iter().all(|&s| s == i16::MAX));
+    }
+}
@@ -0,0 +1,224 @@
+use crate::clamp_to_i16;
+
+const MAX_SAMPLE: f32 = i16::MAX as f32;
+
+/// How a `BlipBuf` brings its output into 16-bit range.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputStage {
+    /// Clamps samples to 16-bit range. Transparent up to full scale, but harsh
+    /// once samples exceed it.
+    #[default]
+    HardClamp,
+    /// Passes samples up to `threshold` unchanged, and bends louder samples into
+    /// the remaining headroom with a tanh curve, which distorts more gently.
+    SoftClip {
+        /// Largest magnitude that is passed unchanged.
+        threshold: u16,
+    },
+    /// Looks `lookahead` samples ahead and lowers the gain in time for louder
+    /// samples to fit, then raises it back to unity over `release` samples.
+    /// Whatever still exceeds 16-bit range is clamped. Delays output by
+    /// `lookahead` samples: the last that many samples of ended time frames
+    /// can't be read until later frames are ended, so output doesn't depend on
+    /// when it is read.
+    Limiter {
+        /// Number of samples to look ahead, and to lower the gain over.
+        lookahead: u16,
+        /// Number of samples to recover from a gain of zero back to unity.
+        release: u32,
+    },
+}
+
+/* Output stage with its state */
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Output {
+    pub(crate) stage: OutputStage,
+    pub(crate) clipped: usize,
+    gain: f32,
+}
+
+impl Output {
+    pub(crate) fn new() -> Self {
+        Self {
+            gain: 1.0,
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.clipped = 0;
+        self.gain = 1.0;
+    }
+
+    /// Number of samples after each sample that `limit()` wants to see.
+    pub(crate) fn lookahead(&self) -> usize {
+        match self.stage {
+            OutputStage::Limiter { lookahead, .. } => lookahead as usize,
+            _ => 0,
+        }
+    }
+
+    /// Converts an unclamped sample, for stages without lookahead.
+    #[inline]
+    pub(crate) fn shape(&mut self, raw: i32) -> i16 {
+        let s = clamp_to_i16(raw);
+        if s != raw {
+            self.clipped += 1;
+        }
+
+        match self.stage {
+            OutputStage::SoftClip { threshold } if raw.unsigned_abs() > threshold as u32 => {
+                let threshold = threshold.min(i16::MAX as u16 - 1) as f32;
+                let headroom = MAX_SAMPLE - threshold;
+                let over = (raw.unsigned_abs() as f32 - threshold) / headroom;
+                let s = (threshold + headroom * over.tanh()).round() as i32;
+                (s * raw.signum()) as i16
+            }
+            _ => s as i16,
+        }
+    }
+
+    /// Converts the unclamped sample `window[0]`, given the samples following it.
+    pub(crate) fn limit(&mut self, window: &[i32]) -> i16 {
+        let OutputStage::Limiter { lookahead, release } = self.stage else {
+            return self.shape(window[0]);
+        };
+
+        /* Lower the gain linearly, so it just fits each upcoming sample when
+        that sample is reached */
+        let span = lookahead as f32 + 1.0;
+        let mut target = 1.0f32;
+        for (distance, &raw) in window.iter().enumerate() {
+            let peak = raw.unsigned_abs() as f32;
+            if peak > MAX_SAMPLE {
+                let needed = MAX_SAMPLE / peak;
+                target = target.min(needed + (1.0 - needed) * distance as f32 / span);
+            }
+        }
+
+        let recovery = if release == 0 {
+            1.0
+        } else {
+            1.0 / release as f32
+        };
+        self.gain = target.min(self.gain + recovery);
+
+        let raw = window[0];
+        if clamp_to_i16(raw) != raw {
+            self.clipped += 1;
+        }
+        clamp_to_i16((raw as f32 * self.gain).round() as i32) as i16
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::OutputStage;
+    use crate::test_util::{new_blip, square_wave};
+
+    /* Reads all of a square wave played through `stage`, and the number of
+    samples clipped */
+    fn read(amplitude: i32, stage: OutputStage) -> (Vec<i16>, usize) {
+        let mut blip = square_wave(2000, amplitude);
+        blip.set_output_stage(stage);
+        let mut out = Vec::new();
+        blip.read_samples_to_vec(&mut out);
+        (out, blip.clipped_samples())
+    }
+
+    fn longest_run_at_max(samples: &[i16]) -> usize {
+        samples
+            .split(|&s| s != i16::MAX)
+            .map(|run| run.len())
+            .max()
+            .unwrap_or(0)
+    }
+
+    const SOFT: OutputStage = OutputStage::SoftClip { threshold: 16000 };
+    const LIMITER: OutputStage = OutputStage::Limiter {
+        lookahead: 32,
+        release: 4800,
+    };
+
+    #[test]
+    fn transparent_when_quiet() {
+        let (hard, clipped) = read(12000, OutputStage::HardClamp);
+        assert_eq!(clipped, 0);
+        assert_eq!(read(12000, SOFT), (hard.clone(), 0));
+        let held = hard.len() - 32;
+        assert_eq!(read(12000, LIMITER), (hard[..held].to_vec(), 0));
+    }
+
+    #[test]
+    fn loud_output() {
+        let (hard, clipped) = read(50000, OutputStage::HardClamp);
+        assert!(clipped > 100);
+        assert!(longest_run_at_max(&hard) > 30);
+
+        let (soft, soft_clipped) = read(50000, SOFT);
+        assert_eq!(soft_clipped, clipped);
+        assert_eq!(longest_run_at_max(&soft), 0);
+        assert!(soft.iter().any(|&s| s > 30000));
+
+        let (limited, limited_clipped) = read(50000, LIMITER);
+        assert_eq!(limited_clipped, clipped);
+        assert!(longest_run_at_max(&limited) < 3);
+        assert!(limited.iter().any(|&s| s > 30000));
+    }
+
+    #[test]
+    fn limiter_reads_agree() {
+        let (expected, _) = read(50000, LIMITER);
+
+        let mut blip = square_wave(2000, 50000);
+        blip.set_output_stage(LIMITER);
+        let mut peeked = vec![0i16; 700];
+        blip.peek_samples(&mut peeked);
+        assert_eq!(peeked, expected[..700]);
+
+        let mut out: Vec<i16> = blip.drain().take(333).collect();
+        blip.read_samples_to_vec(&mut out);
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn limiter_holds_back_lookahead() {
+        let mut blip = square_wave(2000, 50000);
+        blip.set_output_stage(LIMITER);
+        assert_eq!(blip.samples_avail(), 960 - 32);
+        assert_eq!(blip.clocks_needed(10), Ok(209));
+        blip.read_samples_to_vec(&mut Vec::new());
+        assert_eq!(blip.samples_avail(), 0);
+        assert_eq!(blip.free_samples(), 4800 - 32);
+
+        let mut blip = new_blip(1000000.0);
+        blip.set_output_stage(LIMITER);
+        blip.end_frame(blip.clocks_needed(10).unwrap()).unwrap();
+        assert_eq!(blip.samples_avail(), 10);
+    }
+
+    #[test]
+    fn limiter_reads_agree_across_frames() {
+        /* Loud steps right at frame starts need lookahead into the next frame */
+        let frames = |read_each_frame: bool| {
+            let mut blip = new_blip(1000000.0);
+            blip.set_output_stage(LIMITER);
+            let mut out = Vec::new();
+            for frame in 0..20 {
+                let delta = if frame % 2 == 0 { 60000 } else { -60000 };
+                blip.add_delta(0, delta).unwrap();
+                blip.end_frame(3333).unwrap();
+                if read_each_frame {
+                    blip.read_samples_to_vec(&mut out);
+                }
+            }
+            blip.read_samples_to_vec(&mut out);
+            (out, blip.output_hash())
+        };
+        let (each, each_hash) = frames(true);
+        let (once, once_hash) = frames(false);
+        assert_eq!(each, once);
+        assert_eq!(each_hash, once_hash);
+        assert!(longest_run_at_max(&each) < 3);
+    }
+}
@@ -0,0 +1,121 @@
+use crate::{Accumulator, BlipBuf, Producer};
+
+/// Dynamic rate control, for emulators that synchronize to video and would
+/// otherwise slowly drift against the audio device's clock.
+///
+/// Once per frame, `update()` looks at how full the output queue is and nudges
+/// the buffer's sample rate by at most the configured number of parts per
+/// million: up when the queue runs low, down when it runs high. This keeps
+/// the queue around half full, and thus latency bounded, while the pitch
+/// changes stay far too small to hear.
+pub struct RateController {
+    clock_rate: f64,
+    sample_rate: f64,
+    max_deviation: f64,
+    ratio: f64,
+}
+
+impl RateController {
+    /// Creates controller for a buffer with the given nominal rates, as passed to
+    /// `BlipBuf::set_rates()`, that may deviate at most `max_ppm` parts per
+    /// million from `sample_rate`. Around 5000 is a good choice.
+    pub fn new(clock_rate: f64, sample_rate: f64, max_ppm: u32) -> Self {
+        Self {
+            clock_rate,
+            sample_rate,
+            max_deviation: max_ppm as f64 / 1_000_000.0,
+            ratio: 1.0,
+        }
+    }
+
+    /// Changes the nominal rates. Takes effect on the next `update()`.
+    pub fn set_rates(&mut self, clock_rate: f64, sample_rate: f64) {
+        self.clock_rate = clock_rate;
+        self.sample_rate = sample_rate;
+    }
+
+    /// Sets the rates of `blip`, given that the queue it feeds currently holds
+    /// `fill` out of at most `capacity` samples.
+    /// Returns an error if the adjusted rates are out of range for `blip`
+    pub fn update<T: Accumulator>(
+        &mut self,
+        blip: &mut BlipBuf<T>,
+        fill: usize,
+        capacity: usize,
+    ) -> Result<(), &'static str> {
+        self.adjust(fill, capacity);
+        blip.set_rates(self.clock_rate, self.adjusted_sample_rate())
+    }
+
+    /// Same as `update()`, using the queue of a split buffer.
+    /// Returns an error if the adjusted rates are out of range for `producer`
+    pub fn update_producer<T: Accumulator>(
+        &mut self,
+        producer: &mut Producer<T>,
+    ) -> Result<(), &'static str> {
+        self.adjust(producer.samples_queued(), producer.capacity());
+        producer.set_rates(self.clock_rate, self.adjusted_sample_rate())
+    }
+
+    fn adjust(&mut self, fill: usize, capacity: usize) {
+        let level = if capacity == 0 {
+            0.5
+        } else {
+            (fill as f64 / capacity as f64).min(1.0)
+        };
+        self.ratio = 1.0 + self.max_deviation * (1.0 - 2.0 * level);
+    }
+
+    /// Sample rate last passed to `BlipBuf::set_rates()`.
+    pub fn adjusted_sample_rate(&self) -> f64 {
+        self.sample_rate * self.ratio
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RateController;
+    use crate::test_util::new_blip;
+    use crate::BlipBuf;
+
+    #[test]
+    fn bounded_adjustment() {
+        let mut blip = BlipBuf::new(4800);
+        let mut rate = RateController::new(1000000.0, 48000.0, 5000);
+
+        rate.update(&mut blip, 0, 1000).unwrap();
+        assert_eq!(rate.adjusted_sample_rate(), 48000.0 * 1.005);
+        rate.update(&mut blip, 2000, 1000).unwrap();
+        assert_eq!(rate.adjusted_sample_rate(), 48000.0 * 0.995);
+        rate.update(&mut blip, 500, 1000).unwrap();
+        assert_eq!(rate.adjusted_sample_rate(), 48000.0);
+    }
+
+    #[test]
+    fn tracks_fast_device() {
+        let blip = new_blip(1000000.0);
+        let (mut producer, mut consumer) = blip.split(4800);
+        let mut rate = RateController::new(1000000.0, 48000.0, 5000);
+
+        /* The device consumes 0.2% faster than the nominal sample rate. Without
+        rate control, the queue would run dry after about 25 seconds. */
+        let temp = &mut [0i16; 1000];
+        let mut wanted = 0.0;
+        for frame in 0..3600 {
+            rate.update_producer(&mut producer).unwrap();
+            producer.end_frame(16667).unwrap();
+
+            wanted += 48096.0 / 60.0;
+            let count = wanted as usize;
+            wanted -= count as f64;
+            if frame >= 3 {
+                consumer.read_samples(&mut temp[..count], false);
+            }
+        }
+
+        assert_eq!(consumer.underruns(), 0);
+        assert_eq!(consumer.overruns(), 0);
+        assert!(consumer.samples_avail() > 1000);
+        assert!(consumer.samples_avail() < 3800);
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::VecDeque;
+
+use crate::{Accumulator, BlipBuf};
+
+/// History of recent output, for playing it backwards while the emulator
+/// rewinds. Every read through `capture()` or `push()` is kept as one chunk,
+/// normally one time frame. `pop_reversed()` then takes chunks back from the
+/// most recent one, reversed, and crossfades them into each other so the
+/// boundaries don't click.
+pub struct RewindBuffer {
+    samples: VecDeque<i16>,
+    chunks: VecDeque<usize>,
+    max_samples: usize,
+    crossfade: usize,
+    /* End of the last reversed chunk, held back to fade into the next one */
+    pending: Vec<i16>,
+}
+
+impl RewindBuffer {
+    /// Creates history that holds at most `max_samples` samples, dropping the
+    /// oldest chunks to make room, and crossfades reversed chunks over
+    /// `crossfade` samples.
+    pub fn new(max_samples: usize, crossfade: usize) -> Self {
+        Self {
+            samples: VecDeque::with_capacity(max_samples),
+            chunks: VecDeque::new(),
+            max_samples,
+            crossfade,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Same as `BlipBuf::read_samples()` with `stereo` false, but also keeps the
+    /// samples read as a new chunk. Returns number of samples read.
+    pub fn capture<T: Accumulator>(&mut self, blip: &mut BlipBuf<T>, buf: &mut [i16]) -> usize {
+        let count = blip.read_samples(buf, false);
+        self.push(&buf[..count]);
+        count
+    }
+
+    /// Keeps `samples` as a new chunk. A chunk longer than the history can hold
+    /// is cut to its most recent samples.
+    pub fn push(&mut self, samples: &[i16]) {
+        let samples = &samples[samples.len().saturating_sub(self.max_samples)..];
+        if samples.is_empty() {
+            return;
+        }
+
+        while self.samples.len() + samples.len() > self.max_samples {
+            let oldest = self.chunks.pop_front().unwrap();
+            self.samples.drain(..oldest);
+        }
+        self.samples.extend(samples);
+        self.chunks.push_back(samples.len());
+    }
+
+    /// Number of samples held.
+    pub fn samples_held(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Number of chunks held.
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// Removes the most recent chunk and appends it reversed to `out`, faded in
+    /// from the end of the previous reversed chunk. The last `crossfade` samples
+    /// are held back to fade into the next one, until `end_rewind()`. Returns
+    /// number of samples appended, or 0 if no chunks are left.
+    pub fn pop_reversed(&mut self, out: &mut Vec<i16>) -> usize {
+        let Some(len) = self.chunks.pop_back() else {
+            return 0;
+        };
+        let start = out.len();
+        let mut chunk = self.samples.drain(self.samples.len() - len..).rev();
+
+        /* Fade over whatever overlaps the held back samples */
+        let fade = self.pending.len().min(len);
+        let (kept, faded) = self.pending.split_at(self.pending.len() - fade);
+        out.extend_from_slice(kept);
+        let span = (fade + 1) as f32;
+        for (i, (&from, to)) in faded.iter().zip(&mut chunk).enumerate() {
+            let t = (i + 1) as f32 / span;
+            out.push((from as f32 + (to as f32 - from as f32) * t).round() as i16);
+        }
+        out.extend(chunk);
+
+        let held = self.crossfade.min(len - fade);
+        self.pending.clear();
+        self.pending.extend(out.drain(out.len() - held..));
+        out.len() - start
+    }
+
+    /// Appends the samples held back by `pop_reversed()` to `out`, ending the
+    /// reversed playback. Returns number of samples appended.
+    pub fn end_rewind(&mut self, out: &mut Vec<i16>) -> usize {
+        out.extend_from_slice(&self.pending);
+        let count = self.pending.len();
+        self.pending.clear();
+        count
+    }
+
+    /// Removes all chunks and held back samples.
+    pub fn clear(&mut self) {
+        self.samples.clear();
+        self.chunks.clear();
+        self.pending.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RewindBuffer;
+    use crate::test_util::new_blip;
+
+    #[test]
+    fn bounded_history() {
+        let mut rewind = RewindBuffer::new(1000, 0);
+        for i in 0..5 {
+            rewind.push(&[i; 300]);
+        }
+        assert_eq!(rewind.chunk_count(), 3);
+        assert_eq!(rewind.samples_held(), 900);
+        rewind.push(&[9; 2000]);
+        assert_eq!(rewind.chunk_count(), 1);
+        assert_eq!(rewind.samples_held(), 1000);
+    }
+
+    #[test]
+    fn reversed_capture() {
+        let mut blip = new_blip(1000000.0);
+        let mut rewind = RewindBuffer::new(4800, 0);
+        let mut forward = Vec::new();
+        let buf = &mut [0i16; 1000];
+        for frame in 0..3 {
+            blip.add_delta(frame * 1000, 10000).unwrap();
+            blip.end_frame(16667).unwrap();
+            let count = rewind.capture(&mut blip, buf);
+            forward.extend_from_slice(&buf[..count]);
+        }
+
+        let mut out = Vec::new();
+        while rewind.pop_reversed(&mut out) > 0 {}
+        assert_eq!(rewind.end_rewind(&mut out), 0);
+        forward.reverse();
+        assert_eq!(out, forward);
+    }
+
+    #[test]
+    fn crossfades_boundaries() {
+        let mut rewind = RewindBuffer::new(1000, 50);
+        rewind.push(&[1000; 200]);
+        rewind.push(&[-1000; 200]);
+
+        let mut out = Vec::new();
+        assert_eq!(rewind.pop_reversed(&mut out), 150);
+        assert_eq!(rewind.pop_reversed(&mut out), 150);
+        assert_eq!(rewind.pop_reversed(&mut out), 0);
+        assert_eq!(rewind.end_rewind(&mut out), 50);
+        assert_eq!(out.len(), 350);
+
+        assert!(out[..150].iter().all(|&s| s == -1000));
+        assert!(out[150..200].windows(2).all(|w| w[0] < w[1]));
+        assert!(out[200..].iter().all(|&s| s == 1000));
+    }
+}
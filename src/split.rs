@@ -0,0 +1,317 @@
+use std::ops::Deref;
+use std::sync::atomic::{AtomicI16, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::{Accumulator, BlipBuf, ClockDomain, OutputStage, TurboMode};
+
+/* Single-producer single-consumer ring of finished samples. `head` and `tail`
+count samples read and written; they only ever grow, and are only stored by
+the consumer and the producer, respectively. */
+struct Ring {
+    slots: Box<[AtomicI16]>,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    underruns: AtomicU64,
+    overruns: AtomicU64,
+}
+
+impl Ring {
+    fn len(&self) -> usize {
+        let tail = self.tail.load(Ordering::Acquire);
+        let head = self.head.load(Ordering::Acquire);
+        tail.wrapping_sub(head)
+    }
+}
+
+/// Delta side of a split `BlipBuf`, for the emulation thread. Deltas are added
+/// as usual; `end_frame()` then moves the finished samples to the `Consumer`.
+/// Dereferences to the underlying `BlipBuf` for queries, but samples can only
+/// be read through the `Consumer`.
+pub struct Producer<T: Accumulator = i32> {
+    blip: BlipBuf<T>,
+    ring: Arc<Ring>,
+    scratch: Vec<i16>,
+}
+
+/// Read side of a split `BlipBuf`, for the audio thread. Never blocks or
+/// allocates.
+pub struct Consumer {
+    ring: Arc<Ring>,
+}
+
+impl<T: Accumulator> BlipBuf<T> {
+    /// Splits buffer into a `Producer` and `Consumer` that can be used from two
+    /// different threads without locking. Finished samples are passed through a
+    /// wait-free queue holding at most `capacity` samples.
+    pub fn split(self, capacity: usize) -> (Producer<T>, Consumer) {
+        let ring = Arc::new(Ring {
+            slots: (0..capacity).map(|_| AtomicI16::new(0)).collect(),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            underruns: AtomicU64::new(0),
+            overruns: AtomicU64::new(0),
+        });
+        let producer = Producer {
+            blip: self,
+            ring: ring.clone(),
+            scratch: Vec::new(),
+        };
+        (producer, Consumer { ring })
+    }
+}
+
+impl<T: Accumulator> Producer<T> {
+    /// Same as `BlipBuf::end_frame()`, then queues all available samples for the
+    /// `Consumer`. Samples that don't fit in the queue are dropped and counted
+    /// as overruns.
+    /// Returns an error if clock_duration exceeds the buffer's capacity
+    pub fn end_frame(&mut self, clock_duration: u32) -> Result<(), &'static str> {
+        self.blip.end_frame(clock_duration)?;
+
+        self.scratch.resize(self.blip.samples_avail() as usize, 0);
+        let count = self.blip.read_samples(&mut self.scratch, false);
+
+        let ring = &*self.ring;
+        let capacity = ring.slots.len();
+        let tail = ring.tail.load(Ordering::Relaxed);
+        let head = ring.head.load(Ordering::Acquire);
+        let free = capacity - tail.wrapping_sub(head);
+
+        let queued = count.min(free);
+        for (i, &s) in self.scratch[..queued].iter().enumerate() {
+            ring.slots[tail.wrapping_add(i) % capacity].store(s, Ordering::Relaxed);
+        }
+        ring.tail
+            .store(tail.wrapping_add(queued), Ordering::Release);
+
+        if queued < count {
+            ring.overruns
+                .fetch_add((count - queued) as u64, Ordering::Relaxed);
+        }
+
+        Ok(())
+    }
+
+    /// Same as `BlipBuf::set_rates()`.
+    pub fn set_rates(&mut self, clock_rate: f64, sample_rate: f64) -> Result<(), &'static str> {
+        self.blip.set_rates(clock_rate, sample_rate)
+    }
+
+    /// Same as `BlipBuf::add_delta()`.
+    pub fn add_delta(&mut self, clock_time: u32, delta: i32) -> Result<(), &'static str> {
+        self.blip.add_delta(clock_time, delta)
+    }
+
+    /// Same as `BlipBuf::add_delta_fractional()`.
+    pub fn add_delta_fractional(
+        &mut self,
+        clock_time: u32,
+        frac: u16,
+        delta: i32,
+    ) -> Result<(), &'static str> {
+        self.blip.add_delta_fractional(clock_time, frac, delta)
+    }
+
+    /// Same as `BlipBuf::clock_domain()`.
+    pub fn clock_domain(
+        &mut self,
+        clock_rate: f64,
+        sample_rate: f64,
+    ) -> Result<ClockDomain, &'static str> {
+        self.blip.clock_domain(clock_rate, sample_rate)
+    }
+
+    /// Same as `BlipBuf::set_domain_rates()`.
+    pub fn set_domain_rates(
+        &mut self,
+        domain: ClockDomain,
+        clock_rate: f64,
+        sample_rate: f64,
+    ) -> Result<(), &'static str> {
+        self.blip.set_domain_rates(domain, clock_rate, sample_rate)
+    }
+
+    /// Same as `BlipBuf::add_delta_in()`.
+    pub fn add_delta_in(
+        &mut self,
+        domain: ClockDomain,
+        clock_time: u32,
+        delta: i32,
+    ) -> Result<(), &'static str> {
+        self.blip.add_delta_in(domain, clock_time, delta)
+    }
+
+    /// Same as `BlipBuf::add_delta_fast()`.
+    pub fn add_delta_fast(&mut self, clock_time: u32, delta: i32) -> Result<(), &'static str> {
+        self.blip.add_delta_fast(clock_time, delta)
+    }
+
+    /// Same as `BlipBuf::add_slope_change()`.
+    pub fn add_slope_change(
+        &mut self,
+        clock_time: u32,
+        delta_slope: i32,
+    ) -> Result<(), &'static str> {
+        self.blip.add_slope_change(clock_time, delta_slope)
+    }
+
+    /// Same as `BlipBuf::set_max_capacity()`.
+    pub fn set_max_capacity(&mut self, sample_count: u32) {
+        self.blip.set_max_capacity(sample_count)
+    }
+
+    /// Same as `BlipBuf::set_output_stage()`.
+    pub fn set_output_stage(&mut self, stage: OutputStage) {
+        self.blip.set_output_stage(stage)
+    }
+
+    /// Same as `BlipBuf::set_turbo()`.
+    pub fn set_turbo(&mut self, speed: u32, mode: TurboMode) -> Result<(), &'static str> {
+        self.blip.set_turbo(speed, mode)
+    }
+
+    /// Number of samples queued for the `Consumer`.
+    pub fn samples_queued(&self) -> usize {
+        self.ring.len()
+    }
+
+    /// Maximum number of samples the queue can hold.
+    pub fn capacity(&self) -> usize {
+        self.ring.slots.len()
+    }
+
+    /// Total number of samples dropped because the queue was full.
+    pub fn overruns(&self) -> u64 {
+        self.ring.overruns.load(Ordering::Relaxed)
+    }
+
+    /// Total number of samples the `Consumer` asked for but didn't get.
+    pub fn underruns(&self) -> u64 {
+        self.ring.underruns.load(Ordering::Relaxed)
+    }
+}
+
+impl<T: Accumulator> Deref for Producer<T> {
+    type Target = BlipBuf<T>;
+
+    fn deref(&self) -> &BlipBuf<T> {
+        &self.blip
+    }
+}
+
+impl Consumer {
+    /// Reads and removes at most `buf.len()` queued samples and writes them to
+    /// `buf`, like `BlipBuf::read_samples()`. If fewer samples are queued than
+    /// requested, the shortfall is counted as underruns. Returns number of
+    /// samples actually read.
+    pub fn read_samples(&mut self, buf: &mut [i16], stereo: bool) -> usize {
+        let step = if stereo { 2 } else { 1 };
+        let wanted = buf.len() / step;
+
+        let ring = &*self.ring;
+        let capacity = ring.slots.len();
+        let head = ring.head.load(Ordering::Relaxed);
+        let tail = ring.tail.load(Ordering::Acquire);
+        let count = wanted.min(tail.wrapping_sub(head));
+
+        for i in 0..count {
+            buf[i * step] = ring.slots[head.wrapping_add(i) % capacity].load(Ordering::Relaxed);
+        }
+        ring.head.store(head.wrapping_add(count), Ordering::Release);
+
+        if count < wanted {
+            ring.underruns
+                .fetch_add((wanted - count) as u64, Ordering::Relaxed);
+        }
+
+        count
+    }
+
+    /// Number of queued samples available for reading.
+    pub fn samples_avail(&self) -> usize {
+        self.ring.len()
+    }
+
+    /// Maximum number of samples the queue can hold.
+    pub fn capacity(&self) -> usize {
+        self.ring.slots.len()
+    }
+
+    /// Total number of samples dropped because the queue was full.
+    pub fn overruns(&self) -> u64 {
+        self.ring.overruns.load(Ordering::Relaxed)
+    }
+
+    /// Total number of samples asked for but not available.
+    pub fn underruns(&self) -> u64 {
+        self.ring.underruns.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test_util::{add_square_wave, new_blip};
+
+    fn read_square_wave(frames: u32) -> Vec<i16> {
+        let mut blip = new_blip(1000000.0);
+        let mut out = Vec::new();
+        for _ in 0..frames {
+            add_square_wave(&mut blip, 500, 5000, 16667);
+            blip.end_frame(16667).unwrap();
+            let mut temp = vec![0i16; blip.samples_avail() as usize];
+            blip.read_samples(&mut temp, false);
+            out.extend_from_slice(&temp);
+        }
+        out
+    }
+
+    #[test]
+    fn threaded() {
+        let blip = new_blip(1000000.0);
+        let (mut producer, mut consumer) = blip.split(100000);
+        let expected = read_square_wave(30);
+
+        let emulator = std::thread::spawn(move || {
+            for _ in 0..30 {
+                for t in (0..16667).step_by(500) {
+                    producer
+                        .add_delta(t, if t % 1000 == 0 { 5000 } else { -5000 })
+                        .unwrap();
+                }
+                producer.end_frame(16667).unwrap();
+            }
+        });
+
+        let mut out = Vec::new();
+        while out.len() < expected.len() {
+            let temp = &mut [0i16; 256];
+            let count =
+                consumer.read_samples(&mut temp[..(expected.len() - out.len()).min(256)], false);
+            out.extend_from_slice(&temp[..count]);
+            if count == 0 {
+                std::thread::yield_now();
+            }
+        }
+        emulator.join().unwrap();
+
+        assert_eq!(out, expected);
+        assert_eq!(consumer.overruns(), 0);
+    }
+
+    #[test]
+    fn counters() {
+        let blip = new_blip(1000000.0);
+        let (mut producer, mut consumer) = blip.split(500);
+
+        producer.end_frame(16667).unwrap();
+        assert_eq!(producer.samples_queued(), 500);
+        assert_eq!(producer.overruns(), 300);
+
+        let temp = &mut [0i16; 1000];
+        assert_eq!(consumer.read_samples(temp, true), 500);
+        assert_eq!(consumer.underruns(), 0);
+        assert_eq!(consumer.read_samples(temp, false), 0);
+        assert_eq!(consumer.underruns(), 1000);
+        assert_eq!(producer.underruns(), 1000);
+    }
+}
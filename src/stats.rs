@@ -0,0 +1,136 @@
+/// Output level statistics of a `BlipBuf`, see `BlipBuf::enable_stats()`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Stats {
+    /// Number of samples measured.
+    pub samples: u64,
+    /// Largest magnitude of any sample.
+    pub peak: u16,
+    /// Root mean square of the samples.
+    pub rms: f64,
+    /// Mean of the samples.
+    pub dc_offset: f64,
+    /// Number of samples that exceeded 16-bit range before the output stage.
+    pub clamped: u64,
+}
+
+/// Which samples `BlipBuf::stats()` covers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatsWindow {
+    /// Samples returned by the most recent read.
+    LastRead,
+    /// All samples read since statistics were enabled or last reset.
+    SinceReset,
+}
+
+pub(crate) struct StatsCollector {
+    window: StatsWindow,
+    samples: u64,
+    peak: u16,
+    sum: i64,
+    sum_squares: u128,
+    clamped: u64,
+}
+
+impl StatsCollector {
+    pub(crate) fn new(window: StatsWindow) -> Self {
+        Self {
+            window,
+            samples: 0,
+            peak: 0,
+            sum: 0,
+            sum_squares: 0,
+            clamped: 0,
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        *self = Self::new(self.window);
+    }
+
+    /// Called at the start of every read.
+    pub(crate) fn begin_read(&mut self) {
+        if self.window == StatsWindow::LastRead {
+            self.reset();
+        }
+    }
+
+    #[inline]
+    pub(crate) fn add(&mut self, s: i16) {
+        self.samples += 1;
+        self.peak = self.peak.max(s.unsigned_abs());
+        self.sum += s as i64;
+        self.sum_squares += (s as i32 * s as i32) as u128;
+    }
+
+    pub(crate) fn add_clamped(&mut self, count: usize) {
+        self.clamped += count as u64;
+    }
+
+    pub(crate) fn stats(&self) -> Stats {
+        let n = self.samples.max(1) as f64;
+        Stats {
+            samples: self.samples,
+            peak: self.peak,
+            rms: (self.sum_squares as f64 / n).sqrt(),
+            dc_offset: self.sum as f64 / n,
+            clamped: self.clamped,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::StatsWindow;
+    use crate::test_util::square_wave;
+
+    #[test]
+    fn disabled_by_default() {
+        let mut blip = square_wave(500, 10000);
+        blip.read_samples(&mut [0i16; 100], false);
+        assert_eq!(blip.stats(), None);
+    }
+
+    #[test]
+    fn matches_output() {
+        let mut blip = square_wave(500, 50000);
+        blip.enable_stats(StatsWindow::SinceReset);
+        let mut out = vec![0i16; 500];
+        blip.read_samples(&mut out[..200], false);
+        let mut drain = blip.drain();
+        for s in &mut out[200..] {
+            *s = drain.next().unwrap();
+        }
+        drop(drain);
+
+        let stats = blip.stats().unwrap();
+        let mean = out.iter().map(|&s| s as f64).sum::<f64>() / 500.0;
+        let rms = (out.iter().map(|&s| (s as f64).powi(2)).sum::<f64>() / 500.0).sqrt();
+        assert_eq!(stats.samples, 500);
+        assert_eq!(stats.peak, i16::MAX as u16);
+        assert!(stats.clamped > 0);
+        assert!((stats.dc_offset - mean).abs() < 1e-6);
+        assert!((stats.rms - rms).abs() < 1e-6);
+
+        blip.reset_stats();
+        assert_eq!(blip.stats().unwrap().samples, 0);
+    }
+
+    #[test]
+    fn last_read() {
+        let mut blip = square_wave(500, 10000);
+        blip.enable_stats(StatsWindow::LastRead);
+        blip.read_samples(&mut [0i16; 300], false);
+        blip.read_samples(&mut [0i16; 20], true);
+        assert_eq!(blip.stats().unwrap().samples, 10);
+        blip.disable_stats();
+        assert_eq!(blip.stats(), None);
+    }
+
+    #[test]
+    fn short_strided_buffer() {
+        let mut blip = square_wave(500, 10000);
+        blip.enable_stats(StatsWindow::LastRead);
+        assert_eq!(blip.read_samples_strided(&mut [0i16; 2], 6, 4), Ok(0));
+        assert_eq!(blip.stats().unwrap().samples, 0);
+    }
+}
@@ -0,0 +1,292 @@
+//! Golden-file testing of sound synthesis: renders a scenario of deltas through
+//! `BlipBuf` and compares the output against a stored file.
+//!
+//! Golden files ending in `.wav` are mono 16-bit WAV files, which makes them easy
+//! to listen to; any other file holds raw little-endian 16-bit samples. On a
+//! mismatch, the first differing sample is reported, and a three-channel WAV
+//! file with the expected output, the actual output and their difference is
+//! written next to the golden file, with `.diff.wav` as extension. Setting the
+//! `BLIP_BUF_REGENERATE` environment variable writes the golden files instead
+//! of comparing against them.
+//!
+//! Only available with the `testing` feature enabled, which enables `wav` as well.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+
+use crate::wav::WavWriter;
+use crate::{BlipBuf, MAX_FRAME};
+
+/// Name of the environment variable that makes `assert_golden()` regenerate
+/// golden files.
+pub const REGENERATE_VAR: &str = "BLIP_BUF_REGENERATE";
+
+/// Time frame of a `Scenario`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Frame {
+    /// Length of the frame in clocks, as passed to `BlipBuf::end_frame()`.
+    pub length: u32,
+    /// Clock times and deltas, as passed to `BlipBuf::add_delta()`.
+    pub deltas: Vec<(u32, i32)>,
+}
+
+/// Rates and time frames to render through a `BlipBuf`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Scenario {
+    /// Input clock rate, as passed to `BlipBuf::set_rates()`.
+    pub clock_rate: f64,
+    /// Output sample rate, as passed to `BlipBuf::set_rates()`.
+    pub sample_rate: f64,
+    /// Time frames, in order.
+    pub frames: Vec<Frame>,
+}
+
+impl Scenario {
+    /// Creates scenario without any time frames.
+    pub fn new(clock_rate: f64, sample_rate: f64) -> Self {
+        Self {
+            clock_rate,
+            sample_rate,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Adds a time frame `length` clocks long, with the given deltas.
+    pub fn frame(&mut self, length: u32, deltas: &[(u32, i32)]) -> &mut Self {
+        self.frames.push(Frame {
+            length,
+            deltas: deltas.to_vec(),
+        });
+        self
+    }
+
+    /// Renders all time frames, reading all available samples after each one.
+    /// Returns an error if the rates are out of range, or if a frame is too long
+    /// for `MAX_FRAME` samples
+    pub fn render(&self) -> Result<Vec<i16>, &'static str> {
+        let mut blip = BlipBuf::new(MAX_FRAME as u32);
+        blip.set_rates(self.clock_rate, self.sample_rate)?;
+
+        let mut out = Vec::new();
+        for frame in &self.frames {
+            for &(time, delta) in &frame.deltas {
+                blip.add_delta(time, delta)?;
+            }
+            blip.end_frame(frame.length)?;
+            blip.read_samples_to_vec(&mut out);
+        }
+        Ok(out)
+    }
+
+    /// Renders the scenario and compares the output against the golden file at
+    /// `path`, see `assert_golden()`.
+    pub fn assert_golden(&self, path: impl AsRef<Path>) {
+        let samples = self.render().expect("scenario can't be rendered");
+        assert_golden(&samples, self.sample_rate as u32, path);
+    }
+}
+
+/// First difference between two sample streams.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    /// Index of the first differing sample.
+    pub index: usize,
+    /// Expected sample at `index`, or `None` if the expected stream ended.
+    pub expected: Option<i16>,
+    /// Actual sample at `index`, or `None` if the actual stream ended.
+    pub actual: Option<i16>,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |s: Option<i16>| s.map_or("end of output".to_string(), |s| s.to_string());
+        write!(
+            f,
+            "sample {} differs: expected {}, got {}",
+            self.index,
+            show(self.expected),
+            show(self.actual)
+        )
+    }
+}
+
+/// Finds the first sample where `actual` differs from `expected`, including
+/// either of them ending early. Returns `None` if they are equal.
+pub fn first_difference(expected: &[i16], actual: &[i16]) -> Option<Mismatch> {
+    let index = expected
+        .iter()
+        .zip(actual)
+        .position(|(a, b)| a != b)
+        .unwrap_or(expected.len().min(actual.len()));
+    if index == expected.len() && index == actual.len() {
+        return None;
+    }
+    Some(Mismatch {
+        index,
+        expected: expected.get(index).copied(),
+        actual: actual.get(index).copied(),
+    })
+}
+
+/// Compares `actual` against the golden file at `path`, or writes it there if
+/// `BLIP_BUF_REGENERATE` is set. `sample_rate` is only used for WAV files.
+///
+/// Panics with the first differing sample if they don't match, after writing
+/// a diff WAV file. Also panics if the golden file can't be read or written.
+pub fn assert_golden(actual: &[i16], sample_rate: u32, path: impl AsRef<Path>) {
+    let path = path.as_ref();
+    if std::env::var_os(REGENERATE_VAR).is_some() {
+        write_golden(path, actual, sample_rate)
+            .unwrap_or_else(|e| panic!("can't write {}: {e}", path.display()));
+        return;
+    }
+
+    let expected = read_golden(path).unwrap_or_else(|e| {
+        panic!(
+            "can't read {}: {e} (set {REGENERATE_VAR} to create it)",
+            path.display()
+        )
+    });
+    if let Some(mismatch) = first_difference(&expected, actual) {
+        let diff = diff_path(path);
+        match write_diff(&diff, &expected, actual, sample_rate) {
+            Ok(()) => panic!(
+                "{}: {mismatch}; diff written to {}",
+                path.display(),
+                diff.display()
+            ),
+            Err(e) => panic!("{}: {mismatch}; can't write diff: {e}", path.display()),
+        }
+    }
+}
+
+/// Reads samples from a golden file, either WAV or raw.
+pub fn read_golden(path: impl AsRef<Path>) -> io::Result<Vec<i16>> {
+    let path = path.as_ref();
+    let bytes = std::fs::read(path)?;
+    let data = if is_wav(path) {
+        wav_data(&bytes)?
+    } else {
+        &bytes[..]
+    };
+    if data.len() % 2 != 0 {
+        return Err(invalid_data("golden file has a partial sample"));
+    }
+    Ok(data
+        .chunks_exact(2)
+        .map(|b| i16::from_le_bytes([b[0], b[1]]))
+        .collect())
+}
+
+/// Writes samples to a golden file, either WAV or raw.
+pub fn write_golden(path: impl AsRef<Path>, samples: &[i16], sample_rate: u32) -> io::Result<()> {
+    let path = path.as_ref();
+    if is_wav(path) {
+        let mut wav = WavWriter::new(BufWriter::new(File::create(path)?), 1, sample_rate)?;
+        wav.write_samples(samples)?;
+        wav.finish()?;
+        Ok(())
+    } else {
+        let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        std::fs::write(path, bytes)
+    }
+}
+
+fn write_diff(path: &Path, expected: &[i16], actual: &[i16], sample_rate: u32) -> io::Result<()> {
+    let mut wav = WavWriter::new(BufWriter::new(File::create(path)?), 3, sample_rate)?;
+    for i in 0..expected.len().max(actual.len()) {
+        let a = expected.get(i).copied().unwrap_or(0);
+        let b = actual.get(i).copied().unwrap_or(0);
+        wav.write_frame(&[a, b, b.saturating_sub(a)])?;
+    }
+    wav.finish()?;
+    Ok(())
+}
+
+fn diff_path(path: &Path) -> PathBuf {
+    path.with_extension("diff.wav")
+}
+
+fn is_wav(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("wav"))
+}
+
+/* Returns the data chunk of a mono 16-bit PCM WAV file */
+fn wav_data(bytes: &[u8]) -> io::Result<&[u8]> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(invalid_data("not a WAV file"));
+    }
+
+    let mut rest = &bytes[12..];
+    let mut format_ok = false;
+    while rest.len() >= 8 {
+        let id = &rest[0..4];
+        let len = u32::from_le_bytes(rest[4..8].try_into().unwrap()) as usize;
+        let body = rest
+            .get(8..8 + len)
+            .ok_or_else(|| invalid_data("WAV chunk is truncated"))?;
+        match id {
+            b"fmt " if body.len() >= 16 => {
+                let format = u16::from_le_bytes([body[0], body[1]]);
+                let channels = u16::from_le_bytes([body[2], body[3]]);
+                let bits = u16::from_le_bytes([body[14], body[15]]);
+                format_ok = format == 1 && channels == 1 && bits == 16;
+            }
+            b"data" if format_ok => return Ok(body),
+            b"data" => return Err(invalid_data("golden WAV must be mono 16-bit PCM")),
+            _ => {}
+        }
+        /* Chunks are padded to an even length */
+        rest = rest.get(8 + len + len % 2..).unwrap_or(&[]);
+    }
+    Err(invalid_data("WAV file has no data chunk"))
+}
+
+fn invalid_data(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{first_difference, read_golden, write_golden, Mismatch, Scenario};
+
+    #[test]
+    fn differences() {
+        assert_eq!(first_difference(&[1, 2, 3], &[1, 2, 3]), None);
+        assert_eq!(
+            first_difference(&[1, 2, 3], &[1, 5, 3]),
+            Some(Mismatch {
+                index: 1,
+                expected: Some(2),
+                actual: Some(5)
+            })
+        );
+        let short = first_difference(&[1, 2, 3], &[1, 2]).unwrap();
+        assert_eq!((short.index, short.actual), (2, None));
+        assert_eq!(
+            short.to_string(),
+            "sample 2 differs: expected 3, got end of output"
+        );
+    }
+
+    #[test]
+    fn golden_round_trip() {
+        let mut scenario = Scenario::new(1000000.0, 48000.0);
+        scenario
+            .frame(20000, &[(0, 10000), (10000, -10000)])
+            .frame(20000, &[(5000, 3000)]);
+        let samples = scenario.render().unwrap();
+        assert_eq!(samples.len(), 1920);
+
+        let dir = std::env::temp_dir();
+        for name in ["blip_buf_golden.wav", "blip_buf_golden.raw"] {
+            let path = dir.join(format!("{}-{name}", std::process::id()));
+            write_golden(&path, &samples, 48000).unwrap();
+            assert_eq!(read_golden(&path).unwrap(), samples);
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+}
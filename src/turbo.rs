@@ -0,0 +1,223 @@
+use std::ops::Range;
+
+use crate::{fixed_t, Accumulator, BlipBuf, RAMP_BITS};
+
+/// How a `BlipBuf` in turbo mode shortens its output, see `BlipBuf::set_turbo()`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TurboMode {
+    /// Outputs one whole time frame out of every `speed`, and drops the others.
+    /// Keeps the pitch, but gaps are as long as the dropped frames.
+    Drop,
+    /// Plays time frames `speed` times faster, which raises the pitch as well.
+    #[default]
+    TimeCompress,
+    /// Outputs the first `1 / speed` of every time frame, and skips the rest.
+    /// Keeps the pitch, with much shorter gaps than `Drop`.
+    Skip,
+}
+
+/* Turbo setting with its state */
+#[derive(Clone, Copy)]
+pub(crate) struct Turbo {
+    speed: u32,
+    mode: TurboMode,
+    /* Frames since the last kept one for Drop, leftover samples for Skip */
+    count: usize,
+}
+
+impl Turbo {
+    pub(crate) fn new() -> Self {
+        Self {
+            speed: 1,
+            mode: TurboMode::default(),
+            count: 0,
+        }
+    }
+
+    /// Clock factor to use in place of `nominal`.
+    pub(crate) fn factor(&self, nominal: fixed_t) -> fixed_t {
+        match self.mode {
+            TurboMode::TimeCompress => (nominal / self.speed as fixed_t).max(1),
+            _ => nominal,
+        }
+    }
+
+    /// Number of samples to keep of a time frame `len` samples long.
+    fn keep(&mut self, len: usize) -> usize {
+        let speed = self.speed as usize;
+        match self.mode {
+            TurboMode::TimeCompress => len,
+            TurboMode::Drop => {
+                let kept = self.count == 0;
+                self.count = (self.count + 1) % speed;
+                if kept {
+                    len
+                } else {
+                    0
+                }
+            }
+            TurboMode::Skip => {
+                let total = self.count + len;
+                self.count = total % speed;
+                total / speed
+            }
+        }
+    }
+}
+
+impl<T: Accumulator> BlipBuf<T> {
+    /// Puts buffer into turbo mode, for fast-forwarding: the emulator runs `speed`
+    /// times as many time frames per second as usual, and the buffer shortens
+    /// them as `mode` says, so the output keeps its normal length. A `speed` of 1
+    /// turns turbo mode off. Should be called between time frames.
+    ///
+    /// With `TurboMode::TimeCompress`, clock domains keep their own rates, so
+    /// multiply their clock rates by `speed` as well, with `set_domain_rates()`.
+    /// Returns an error if speed is zero
+    pub fn set_turbo(&mut self, speed: u32, mode: TurboMode) -> Result<(), &'static str> {
+        if speed == 0 {
+            return Err("turbo speed must not be zero");
+        }
+        self.turbo = Turbo {
+            speed,
+            mode,
+            count: 0,
+        };
+        self.factor = self.turbo.factor(self.nominal_factor);
+        Ok(())
+    }
+
+    /// Current turbo speed and mode.
+    pub fn turbo(&self) -> (u32, TurboMode) {
+        (self.turbo.speed, self.turbo.mode)
+    }
+
+    /* Removes the part of the time frame from `start` to `avail` that turbo mode
+    doesn't keep */
+    pub(crate) fn shorten_frame(&mut self, start: usize) {
+        if self.turbo.speed > 1 {
+            let keep = self.turbo.keep(self.avail - start);
+            self.collapse(start + keep..self.avail);
+        }
+    }
+
+    /* Removes samples in `range`, moving whatever they would have changed the
+    output level by onto the sample after them, so the waveform continues at the
+    same level. */
+    fn collapse(&mut self, range: Range<usize>) {
+        let (start, end) = (range.start, range.end);
+        if start == end {
+            return;
+        }
+
+        let mut level = self.samples[range.clone()]
+            .iter()
+            .map(|&s| s.into())
+            .fold(0i64, i64::saturating_add);
+        if !self.ramps.is_empty() {
+            /* Slopes keep changing the level over the removed samples, too */
+            let mut slope = self.integrator.slope + self.ramps[..start].iter().sum::<i64>();
+            let mut ramp = 0;
+            let mut ramped = 0;
+            for &r in &self.ramps[range.clone()] {
+                slope += r;
+                ramp += r;
+                ramped += slope;
+            }
+            level = level.saturating_add(ramped >> RAMP_BITS);
+
+            let len = self.ramps.len();
+            self.ramps.copy_within(end.., start);
+            self.ramps[len - range.len()..].fill(0);
+            self.ramps[start] += ramp;
+        }
+
+        let len = self.samples.len();
+        self.samples.copy_within(end.., start);
+        self.samples[len - range.len()..].fill(T::default());
+        self.samples[start] = self.samples[start].saturating_add(T::saturate(level));
+
+        self.avail -= range.len();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TurboMode;
+    use crate::test_util::{add_square_wave, new_blip};
+    use crate::BlipBuf;
+
+    const FRAME: u32 = 20000;
+
+    /* Runs `frames` frames of a square wave at `speed` times the normal clock rate */
+    fn run_frames(blip: &mut BlipBuf, frames: u32, speed: u32) {
+        let mut delta = 10000;
+        for _ in 0..frames {
+            delta = add_square_wave(blip, 300 * speed, delta, FRAME * speed);
+            blip.end_frame(FRAME * speed).unwrap();
+        }
+    }
+
+    #[test]
+    fn time_compress() {
+        let mut expected = new_blip(1000000.0);
+        run_frames(&mut expected, 1, 1);
+        let mut turbo = new_blip(1000000.0);
+        turbo.set_turbo(4, TurboMode::TimeCompress).unwrap();
+        assert_eq!(turbo.turbo(), (4, TurboMode::TimeCompress));
+        run_frames(&mut turbo, 1, 4);
+
+        assert_eq!(turbo.samples_avail(), expected.samples_avail());
+        let (mut a, mut b) = (Vec::new(), Vec::new());
+        expected.read_samples_to_vec(&mut a);
+        turbo.read_samples_to_vec(&mut b);
+        for (a, b) in a.iter().zip(&b) {
+            assert!((a - b).abs() <= 1);
+        }
+
+        turbo.set_turbo(1, TurboMode::TimeCompress).unwrap();
+        run_frames(&mut turbo, 1, 1);
+        assert_eq!(turbo.samples_avail(), 960);
+    }
+
+    #[test]
+    fn skip() {
+        let mut expected = new_blip(1000000.0);
+        run_frames(&mut expected, 1, 1);
+        let mut turbo = new_blip(1000000.0);
+        turbo.set_turbo(4, TurboMode::Skip).unwrap();
+        run_frames(&mut turbo, 1, 1);
+        assert_eq!(turbo.samples_avail(), 240);
+
+        let a = &mut [0i16; 240];
+        let b = &mut [0i16; 240];
+        expected.read_samples(a, false);
+        turbo.read_samples(b, false);
+        assert_eq!(a, b);
+
+        run_frames(&mut turbo, 3, 1);
+        assert_eq!(turbo.samples_avail(), 720);
+        assert!(turbo.set_turbo(0, TurboMode::Skip).is_err());
+    }
+
+    #[test]
+    fn drop_frames() {
+        let mut turbo = new_blip(1000000.0);
+        turbo.set_turbo(3, TurboMode::Drop).unwrap();
+        turbo.end_frame(FRAME).unwrap();
+        assert_eq!(turbo.samples_avail(), 960);
+        turbo.skip_samples(960);
+
+        /* A step in a dropped frame still shows in the next kept one */
+        turbo.add_delta(FRAME / 2, 8000).unwrap();
+        turbo.end_frame(FRAME).unwrap();
+        turbo.add_slope_change(0, 0).unwrap();
+        turbo.end_frame(FRAME).unwrap();
+        assert_eq!(turbo.samples_avail(), 0);
+
+        turbo.end_frame(FRAME).unwrap();
+        let buf = &mut [0i16; 960];
+        assert_eq!(turbo.read_samples(buf, false), 960);
+        assert!(buf[10] > 7000);
+    }
+}
@@ -0,0 +1,328 @@
+//! Minimal RIFF/WAVE writer, useful for recording `BlipBuf` output to disk.
+//!
+//! Only available with the `wav` feature enabled.
+
+use std::io::{self, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+
+use crate::{Accumulator, BlipBuf};
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_IEEE_FLOAT: u16 = 3;
+
+mod private {
+    pub trait Sealed {}
+    impl Sealed for i16 {}
+    impl Sealed for f32 {}
+}
+
+/// Sample formats that can be written to a WAV file: `i16` (16-bit PCM) and
+/// `f32` (32-bit IEEE float).
+pub trait Sample: Copy + private::Sealed {
+    #[doc(hidden)]
+    const FORMAT_TAG: u16;
+    #[doc(hidden)]
+    const BITS: u16;
+    #[doc(hidden)]
+    fn extend_le(self, out: &mut Vec<u8>);
+}
+
+impl Sample for i16 {
+    const FORMAT_TAG: u16 = FORMAT_PCM;
+    const BITS: u16 = 16;
+
+    fn extend_le(self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Sample for f32 {
+    const FORMAT_TAG: u16 = FORMAT_IEEE_FLOAT;
+    const BITS: u16 = 32;
+
+    fn extend_le(self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+/// Writes interleaved frames of `S` samples to a WAV stream. Chunk sizes are
+/// written as placeholders and fixed up by `finish()`, so the underlying
+/// writer must be seekable. A writer that is dropped without calling
+/// `finish()` leaves an incomplete header behind.
+pub struct WavWriter<W: Write + Seek, S: Sample = i16> {
+    inner: W,
+    channels: u16,
+    frames: u32,
+    start: u64,
+    scratch: Vec<u8>,
+    _sample: PhantomData<S>,
+}
+
+impl<W: Write + Seek, S: Sample> WavWriter<W, S> {
+    /// Writes a WAV header for `channels` interleaved channels at `sample_rate`
+    /// to `inner`, starting at its current position.
+    /// Returns an error if `channels` is zero, if a frame or a second of frames
+    /// is too large for the header's fields, or if the header can't be written.
+    pub fn new(mut inner: W, channels: u16, sample_rate: u32) -> io::Result<Self> {
+        if channels == 0 {
+            return Err(invalid_input("WAV file needs at least one channel"));
+        }
+
+        let block_align = u16::try_from(channels as u32 * (S::BITS / 8) as u32)
+            .map_err(|_| invalid_input("WAV frame size was exceeded"))?;
+        let byte_rate = sample_rate
+            .checked_mul(block_align as u32)
+            .ok_or_else(|| invalid_input("WAV byte rate was exceeded"))?;
+
+        let start = inner.stream_position()?;
+        let is_float = S::FORMAT_TAG == FORMAT_IEEE_FLOAT;
+
+        let mut header = Vec::with_capacity(58);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(b"WAVE");
+
+        header.extend_from_slice(b"fmt ");
+        header.extend_from_slice(&(if is_float { 18u32 } else { 16u32 }).to_le_bytes());
+        header.extend_from_slice(&S::FORMAT_TAG.to_le_bytes());
+        header.extend_from_slice(&channels.to_le_bytes());
+        header.extend_from_slice(&sample_rate.to_le_bytes());
+        header.extend_from_slice(&byte_rate.to_le_bytes());
+        header.extend_from_slice(&block_align.to_le_bytes());
+        header.extend_from_slice(&S::BITS.to_le_bytes());
+
+        /* Non-PCM formats need an (empty) extension and a fact chunk */
+        if is_float {
+            header.extend_from_slice(&0u16.to_le_bytes());
+            header.extend_from_slice(b"fact");
+            header.extend_from_slice(&4u32.to_le_bytes());
+            header.extend_from_slice(&0u32.to_le_bytes());
+        }
+
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&0u32.to_le_bytes());
+        inner.write_all(&header)?;
+
+        Ok(Self {
+            inner,
+            channels,
+            frames: 0,
+            start,
+            scratch: Vec::new(),
+            _sample: PhantomData,
+        })
+    }
+
+    /// Number of interleaved channels per frame.
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    /// Number of frames written so far.
+    pub fn frames_written(&self) -> u32 {
+        self.frames
+    }
+
+    /// Writes a single frame. Returns an error if `frame.len()` differs from
+    /// `channels()`.
+    pub fn write_frame(&mut self, frame: &[S]) -> io::Result<()> {
+        if frame.len() != self.channels as usize {
+            return Err(invalid_input("frame length doesn't match channel count"));
+        }
+        self.write_samples(frame)
+    }
+
+    /// Writes interleaved samples for any number of whole frames. Returns an
+    /// error if `samples.len()` isn't a multiple of `channels()`, or if the
+    /// data would exceed the 4 GiB limit of the WAV format.
+    pub fn write_samples(&mut self, samples: &[S]) -> io::Result<()> {
+        let channels = self.channels as usize;
+        if !samples.len().is_multiple_of(channels) {
+            return Err(invalid_input(
+                "sample count isn't a multiple of channel count",
+            ));
+        }
+
+        let frames = self
+            .frames_after(samples.len() / channels)
+            .ok_or_else(|| invalid_input("WAV data size was exceeded"))?;
+
+        self.scratch.clear();
+        for &s in samples {
+            s.extend_le(&mut self.scratch);
+        }
+        self.inner.write_all(&self.scratch)?;
+        self.frames = frames;
+
+        Ok(())
+    }
+
+    /// Fixes up the chunk sizes in the header and returns the underlying
+    /// writer, positioned at the end of the WAV data.
+    pub fn finish(mut self) -> io::Result<W> {
+        let data_len = self
+            .data_len(self.frames)
+            .expect("checked in write_samples");
+        let is_float = S::FORMAT_TAG == FORMAT_IEEE_FLOAT;
+        let header_len: u32 = if is_float { 58 } else { 44 };
+        let end = self.inner.stream_position()?;
+
+        self.patch(4, header_len - 8 + data_len)?;
+        if is_float {
+            self.patch(46, self.frames)?;
+        }
+        self.patch(header_len as u64 - 4, data_len)?;
+
+        self.inner.seek(SeekFrom::Start(end))?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn frames_after(&self, frames: usize) -> Option<u32> {
+        u32::try_from(frames)
+            .ok()
+            .and_then(|n| self.frames.checked_add(n))
+            .filter(|&n| self.data_len(n).is_some())
+    }
+
+    fn data_len(&self, frames: u32) -> Option<u32> {
+        let block_align = self.channels as u32 * (S::BITS / 8) as u32;
+        frames
+            .checked_mul(block_align)
+            .filter(|&n| n <= u32::MAX - 58)
+    }
+
+    fn patch(&mut self, at: u64, value: u32) -> io::Result<()> {
+        self.inner.seek(SeekFrom::Start(self.start + at))?;
+        self.inner.write_all(&value.to_le_bytes())
+    }
+}
+
+impl<T: Accumulator> BlipBuf<T> {
+    /// Reads all available samples and writes them to `writer`, which must be
+    /// a mono writer. Returns number of samples written.
+    /// Returns an error without reading any samples if the writer isn't mono or
+    /// they would exceed the 4 GiB limit of the WAV format. If writing fails
+    /// part way through, samples read before the failure are lost.
+    pub fn drain_to_wav<W: Write + Seek>(
+        &mut self,
+        writer: &mut WavWriter<W, i16>,
+    ) -> io::Result<usize> {
+        if writer.channels() != 1 {
+            return Err(invalid_input("drain_to_wav needs a mono writer"));
+        }
+        if writer.frames_after(self.samples_avail() as usize).is_none() {
+            return Err(invalid_input("WAV data size was exceeded"));
+        }
+
+        let mut total = 0;
+        let temp = &mut [0i16; 1024];
+        while self.samples_avail() > 0 {
+            let count = self.read_samples(temp, false);
+            writer.write_samples(&temp[..count])?;
+            total += count;
+        }
+
+        Ok(total)
+    }
+}
+
+fn invalid_input(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+#[cfg(test)]
+mod test {
+    use super::WavWriter;
+    use crate::test_util::new_blip;
+    use std::io::{Cursor, ErrorKind};
+
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn pcm_header() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), 2, 44100).unwrap();
+        wav.write_frame(&[1i16, -1]).unwrap();
+        wav.write_samples(&[2, -2, 3, -3]).unwrap();
+        assert_eq!(wav.frames_written(), 3);
+        let bytes = wav.finish().unwrap().into_inner();
+
+        assert_eq!(bytes.len(), 44 + 12);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(u32_at(&bytes, 4), 36 + 12);
+        assert_eq!(&bytes[8..16], b"WAVEfmt ");
+        assert_eq!(u32_at(&bytes, 24), 44100);
+        assert_eq!(u32_at(&bytes, 28), 44100 * 4);
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(u32_at(&bytes, 40), 12);
+        assert_eq!(&bytes[44..48], &[1, 0, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn float_header() {
+        let mut wav = WavWriter::<_, f32>::new(Cursor::new(Vec::new()), 1, 48000).unwrap();
+        wav.write_samples(&[0.5, -0.5]).unwrap();
+        let bytes = wav.finish().unwrap().into_inner();
+
+        assert_eq!(bytes.len(), 58 + 8);
+        assert_eq!(u32_at(&bytes, 4), 50 + 8);
+        assert_eq!(u32_at(&bytes, 16), 18);
+        assert_eq!(&bytes[38..42], b"fact");
+        assert_eq!(u32_at(&bytes, 46), 2);
+        assert_eq!(&bytes[50..54], b"data");
+        assert_eq!(u32_at(&bytes, 54), 8);
+        assert_eq!(&bytes[58..62], &0.5f32.to_le_bytes());
+    }
+
+    #[test]
+    fn rejects_partial_frames() {
+        let mut wav = WavWriter::<_, i16>::new(Cursor::new(Vec::new()), 3, 44100).unwrap();
+        assert!(wav.write_frame(&[0, 0]).is_err());
+        assert!(wav.write_samples(&[0, 0, 0, 0]).is_err());
+        assert_eq!(wav.frames_written(), 0);
+    }
+
+    #[test]
+    fn rejects_oversized_frames() {
+        let error = WavWriter::<_, f32>::new(Cursor::new(Vec::new()), 20000, 48000);
+        assert_eq!(error.err().unwrap().kind(), ErrorKind::InvalidInput);
+        let error = WavWriter::<_, i16>::new(Cursor::new(Vec::new()), 2, u32::MAX / 2);
+        assert_eq!(error.err().unwrap().kind(), ErrorKind::InvalidInput);
+
+        let wav = WavWriter::<_, f32>::new(Cursor::new(Vec::new()), 16383, 1000).unwrap();
+        let bytes = wav.finish().unwrap().into_inner();
+        assert_eq!(u32_at(&bytes, 28), 1000 * 65532);
+    }
+
+    #[test]
+    fn drain_to_wav_keeps_samples_that_dont_fit() {
+        let mut blip = new_blip(1000000.0);
+        blip.add_delta(100, 10000).unwrap();
+        blip.end_frame(50000).unwrap();
+        let avail = blip.samples_avail();
+
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), 1, 48000).unwrap();
+        wav.frames = (u32::MAX - 58) / 2 - 10;
+        assert_eq!(
+            blip.drain_to_wav(&mut wav).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+        assert_eq!(blip.samples_avail(), avail);
+        assert_eq!(wav.frames_written(), (u32::MAX - 58) / 2 - 10);
+    }
+
+    #[test]
+    fn drain_to_wav() {
+        let mut blip = new_blip(1000000.0);
+        blip.add_delta(100, 10000).unwrap();
+        blip.end_frame(50000).unwrap();
+        let avail = blip.samples_avail() as usize;
+
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), 1, 48000).unwrap();
+        assert_eq!(blip.drain_to_wav(&mut wav).unwrap(), avail);
+        assert_eq!(blip.samples_avail(), 0);
+        assert_eq!(wav.frames_written() as usize, avail);
+    }
+}
@@ -0,0 +1,34 @@
+extern crate blip_buf;
+
+use blip_buf::testing::Scenario;
+
+const CLOCK_RATE: f64 = 1789773.0;
+const SAMPLE_RATE: f64 = 44100.0;
+
+/* Pulse wave that sweeps down in pitch, with a decaying volume */
+#[test]
+fn pulse_sweep() {
+    let mut scenario = Scenario::new(CLOCK_RATE, SAMPLE_RATE);
+    let clocks = (CLOCK_RATE / 60.0) as u32;
+    let mut time = 0;
+    let mut period = 200;
+    let mut high = false;
+
+    for frame in 0..30 {
+        let volume = 12000 - frame * 300;
+        let mut deltas = Vec::new();
+        while time < clocks {
+            deltas.push((time, if high { -volume } else { volume }));
+            high = !high;
+            time += if high { period / 4 } else { period * 3 / 4 };
+        }
+        time -= clocks;
+        period += 8;
+        scenario.frame(clocks, &deltas);
+    }
+
+    scenario.assert_golden(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/golden/pulse_sweep.wav"
+    ));
+}
@@ -20,7 +20,7 @@ impl Lfsr13 {
         let lfsr = self.state;
         let bit = (lfsr ^ (lfsr >> 1) ^ (lfsr >> 2) ^ (lfsr >> 5)) & 1;
         self.state = (lfsr >> 1) | (bit << 12);
-        return self.state;
+        self.state
     }
 }
 
@@ -35,7 +35,7 @@ fn lfsr() {
     let mut lfsr = Lfsr13::new();
     let mut oldval = lfsr.next();
 
-    for n in 0..60 {
+    for (n, expected) in RESULTS.iter().enumerate() {
         let clocks = CLOCK_RATE as i32 / 60;
         while time < clocks {
             let newval = lfsr.next();
@@ -43,11 +43,11 @@ fn lfsr() {
             blip.add_delta(time as u32, delta).unwrap();
             oldval = newval;
 
-            time = time + period;
+            time += period;
         }
 
         // Add those clocks to buffer and adjust time for next frame
-        time = time - clocks;
+        time -= clocks;
         blip.end_frame(clocks as u32).unwrap();
 
         // Read any output samples now available
@@ -55,9 +55,11 @@ fn lfsr() {
             let temp = &mut [0i16; 1024];
             let count = blip.read_samples(temp, false);
             let target_count = if n == 0 { 79 } else { 80 };
-            assert_eq!(&temp[..count], &RESULTS[n][..target_count]);
+            assert_eq!(&temp[..count], &expected[..target_count]);
         }
     }
+
+    assert_eq!(blip.output_hash(), 0x1b8b_f70d_44ae_cafe);
 }
 
 const RESULTS: [[i16; 80]; 60] = [
@@ -0,0 +1,146 @@
+extern crate blip_buf;
+extern crate proptest;
+
+use blip_buf::{Accumulator, BlipBuf, MAX_FRAME};
+use proptest::prelude::*;
+
+#[derive(Clone, Debug)]
+enum Op {
+    SetRates(f64, f64),
+    AddDelta(u32, i32),
+    AddDeltaFast(u32, i32),
+    EndFrame(u32),
+    ClocksNeeded(u32),
+    ReadSamples(usize, bool),
+    Clear,
+    ClockDomain(f64, f64),
+    AddDeltaIn(u32, i32),
+    DomainClocks(u32),
+}
+
+fn rates() -> impl Strategy<Value = (f64, f64)> {
+    (8000.0..96000.0f64, 1.0..65536.0f64)
+        .prop_map(|(sample_rate, ratio)| (sample_rate * ratio, sample_rate))
+}
+
+/* Clock times are mostly small enough to fit a frame, but may be anything */
+fn clock_time() -> impl Strategy<Value = u32> {
+    prop_oneof![4 => 0..100_000u32, 1 => any::<u32>()]
+}
+
+fn op(delta: impl Strategy<Value = i32> + Clone) -> impl Strategy<Value = Op> {
+    prop_oneof![
+        1 => rates().prop_map(|(clock_rate, sample_rate)| Op::SetRates(clock_rate, sample_rate)),
+        4 => (clock_time(), delta.clone()).prop_map(|(t, d)| Op::AddDelta(t, d)),
+        2 => (clock_time(), delta.clone()).prop_map(|(t, d)| Op::AddDeltaFast(t, d)),
+        2 => clock_time().prop_map(Op::EndFrame),
+        1 => (0..5000u32).prop_map(Op::ClocksNeeded),
+        2 => (0..5000usize, any::<bool>()).prop_map(|(n, stereo)| Op::ReadSamples(n, stereo)),
+        1 => Just(Op::Clear),
+        1 => rates().prop_map(|(clock_rate, sample_rate)| Op::ClockDomain(clock_rate, sample_rate)),
+        2 => (clock_time(), delta).prop_map(|(t, d)| Op::AddDeltaIn(t, d)),
+        1 => clock_time().prop_map(Op::DomainClocks),
+    ]
+}
+
+fn ops(delta: impl Strategy<Value = i32> + Clone) -> impl Strategy<Value = Vec<Op>> {
+    prop::collection::vec(op(delta), 0..64)
+}
+
+/* Applies `ops`, ignoring errors, and returns all samples read */
+fn apply<T: Accumulator>(blip: &mut BlipBuf<T>, ops: &[Op]) -> Vec<i16> {
+    let mut out = Vec::new();
+    let mut domain = None;
+    for op in ops {
+        match *op {
+            Op::SetRates(clock_rate, sample_rate) => {
+                let _ = blip.set_rates(clock_rate, sample_rate);
+            }
+            Op::AddDelta(time, delta) => {
+                let _ = blip.add_delta(time, delta);
+            }
+            Op::AddDeltaFast(time, delta) => {
+                let _ = blip.add_delta_fast(time, delta);
+            }
+            Op::EndFrame(duration) => {
+                let _ = blip.end_frame(duration);
+            }
+            Op::ClocksNeeded(count) => {
+                let _ = blip.clocks_needed(count);
+            }
+            Op::ReadSamples(count, stereo) => {
+                let mut buf = vec![0i16; count];
+                let read = blip.read_samples(&mut buf, stereo);
+                let step = if stereo { 2 } else { 1 };
+                out.extend(buf.iter().step_by(step).take(read));
+            }
+            Op::Clear => blip.clear(),
+            Op::ClockDomain(clock_rate, sample_rate) => {
+                domain = blip.clock_domain(clock_rate, sample_rate).ok();
+            }
+            Op::AddDeltaIn(time, delta) => {
+                if let Some(domain) = domain {
+                    let _ = blip.add_delta_in(domain, time, delta);
+                }
+            }
+            Op::DomainClocks(duration) => {
+                if let Some(domain) = domain {
+                    let _ = blip.domain_clocks(domain, duration);
+                }
+            }
+        }
+    }
+    out
+}
+
+proptest! {
+    #[test]
+    fn never_panics(size in 0..8000u32, ops in ops(any::<i32>())) {
+        apply(&mut BlipBuf::new(size), &ops);
+        apply(&mut BlipBuf::<i64>::with_accumulator(size), &ops);
+    }
+
+    #[test]
+    fn clocks_needed_predicts_samples(
+        size in 1..8000u32,
+        (clock_rate, sample_rate) in rates(),
+        ops in ops(any::<i32>()),
+        wanted in 0..8000u32,
+    ) {
+        let mut blip = BlipBuf::new(size);
+        blip.set_rates(clock_rate, sample_rate).unwrap();
+        apply(&mut blip, &ops);
+
+        let avail = blip.samples_avail();
+        if let Ok(clocks) = blip.clocks_needed(wanted) {
+            blip.end_frame(clocks).unwrap();
+            prop_assert_eq!(blip.samples_avail(), avail + wanted);
+        } else {
+            prop_assert!(avail + wanted > size || wanted as u64 > MAX_FRAME);
+        }
+    }
+
+    #[test]
+    fn max_clock_time_is_exact(
+        size in 1..8000u32,
+        (clock_rate, sample_rate) in rates(),
+        ops in ops(any::<i32>()),
+    ) {
+        let mut blip = BlipBuf::new(size);
+        blip.set_rates(clock_rate, sample_rate).unwrap();
+        apply(&mut blip, &ops);
+
+        let max = blip.max_clock_time();
+        prop_assert!(blip.add_delta(max, 1).is_ok());
+        if max < u32::MAX {
+            prop_assert!(blip.add_delta(max + 1, 1).is_err());
+        }
+    }
+
+    #[test]
+    fn silence_in_silence_out(size in 0..8000u32, ops in ops(Just(0))) {
+        let mut blip = BlipBuf::new(size);
+        let out = apply(&mut blip, &ops);
+        prop_assert!(out.iter().all(|&s| s == 0));
+    }
+}